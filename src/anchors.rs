@@ -0,0 +1,302 @@
+use crate::DockerComposeFile;
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::LazyLock;
+
+static TOP_LEVEL_ANCHOR_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?m)^["']?(?<key>[^\s#"':][^"':]*)["']?:[ \t]*&(?<anchor>[^\s,\[\]{}]+)"#)
+        .expect("should be able to compile top-level anchor regex")
+});
+
+static ALIAS_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:^|[\s\[{,:])\*(?<alias>[^\s,\[\]{}]+)")
+        .expect("should be able to compile alias regex")
+});
+
+/// The anchors defined directly on top-level keys of a compose file, e.g. `x-logging: &logging`,
+/// as `(key, anchor)` pairs in the order they appear.
+pub fn top_level_anchors(text: &str) -> Vec<(String, String)> {
+    TOP_LEVEL_ANCHOR_RE
+        .captures_iter(text)
+        .map(|captures| {
+            (
+                captures["key"].trim().to_string(),
+                captures["anchor"].to_string(),
+            )
+        })
+        .collect()
+}
+
+/// The names of every alias (`*name`) used in a piece of YAML.
+pub fn aliases_in(text: &str) -> BTreeSet<String> {
+    ALIAS_RE
+        .captures_iter(text)
+        .map(|captures| captures["alias"].to_string())
+        .collect()
+}
+
+pub(crate) fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+pub(crate) fn is_blank_or_comment(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.is_empty() || trimmed.starts_with('#')
+}
+
+pub(crate) fn key_of(line: &str) -> Option<&str> {
+    let (key, _) = line.trim_start().split_once(':')?;
+    Some(key.trim().trim_matches(|c| c == '"' || c == '\''))
+}
+
+/// Slice out the block of `lines` that starts at the key `name` indented by `indent`, up to
+/// the next line that is indented as much or less.
+fn block_at<'a>(lines: &[&'a str], indent: usize, name: &str) -> Option<Vec<&'a str>> {
+    let start = lines.iter().position(|line| {
+        !is_blank_or_comment(line) && indentation(line) == indent && key_of(line) == Some(name)
+    })?;
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| !is_blank_or_comment(line) && indentation(line) <= indent)
+        .map_or(lines.len(), |end| start + 1 + end);
+    Some(lines[start..end].to_vec())
+}
+
+/// The raw text of a top-level key's block, including the key itself.
+pub fn top_level_block(text: &str, key: &str) -> Option<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    block_at(&lines, 0, key).map(|block| block.join("\n"))
+}
+
+/// The raw text of a service's block under the top-level `services` key, including its name.
+///
+/// This only understands block-style YAML, which is what compose files are written in.
+pub fn service_block(text: &str, name: &str) -> Option<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let services = block_at(&lines, 0, "services")?;
+    let indent = services[1..]
+        .iter()
+        .find(|line| !is_blank_or_comment(line))
+        .map(|line| indentation(line))?;
+    block_at(&services[1..], indent, name).map(|block| block.join("\n"))
+}
+
+/// The top-level keys whose anchors the given services reference, directly or through other
+/// anchored top-level keys, in the order they're defined in the file.
+pub fn referenced_extensions(text: &str, services: &[String]) -> Vec<String> {
+    let anchors = top_level_anchors(text);
+
+    let mut pending: Vec<String> = services
+        .iter()
+        .filter_map(|service| service_block(text, service))
+        .flat_map(|block| aliases_in(&block))
+        .collect();
+    let mut referenced = BTreeSet::new();
+    while let Some(alias) = pending.pop() {
+        for (key, _) in anchors.iter().filter(|(_, anchor)| *anchor == alias) {
+            if referenced.insert(key.clone())
+                && let Some(block) = top_level_block(text, key)
+            {
+                pending.extend(aliases_in(&block));
+            }
+        }
+    }
+    anchors
+        .into_iter()
+        .map(|(key, _)| key)
+        .filter(|key| referenced.contains(key) && key != "services")
+        .collect()
+}
+
+impl DockerComposeFile {
+    /// The anchored top-level entries (usually `x-` extensions) that `services` reference,
+    /// directly or through each other, in the order they're defined in the file.
+    ///
+    /// The services themselves come out of [`DockerComposeFile::get_service`] with their
+    /// aliases and `<<` merge keys resolved, so these are only needed to carry the anchors'
+    /// definitions along with them. Empty for files that weren't parsed from text.
+    pub fn referenced_extensions(&self, services: &[String]) -> serde_yaml::Mapping {
+        let Some(text) = &self.raw else {
+            return serde_yaml::Mapping::new();
+        };
+        referenced_extensions(text, services)
+            .into_iter()
+            .filter_map(|key| {
+                let value = self.other.get(key.as_str())?.clone();
+                Some((key.into(), value))
+            })
+            .collect()
+    }
+}
+
+/// Collects the anchored top-level blocks (usually `x-` extensions) that selected services
+/// reference, so they can be carried into the merged output next to the services.
+///
+/// The services themselves already have their aliases resolved, so hoisting keeps the output
+/// readable and reusable rather than being required for it to work. When two sources hoist
+/// different contents under the same key, the later one is renamed with a numeric suffix.
+#[derive(Debug, Default, Clone)]
+pub struct AnchorHoister {
+    hoisted: serde_yaml::Mapping,
+    origins: HashMap<String, String>,
+}
+
+impl AnchorHoister {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hoist the blocks `services` in `file` reference, returning a warning for every block
+    /// that had to be renamed to avoid a clash. `origin` describes the file in those warnings.
+    pub fn hoist(
+        &mut self,
+        file: &DockerComposeFile,
+        services: &[String],
+        origin: &str,
+    ) -> Vec<String> {
+        let mut warnings = vec![];
+        for (key, value) in file.referenced_extensions(services) {
+            let Some(key) = key.as_str() else {
+                continue;
+            };
+            let mut target = key.to_string();
+            let mut suffix = 1;
+            loop {
+                match self.hoisted.get(target.as_str()) {
+                    None => break,
+                    Some(existing) if *existing == value => break,
+                    Some(_) => {
+                        suffix += 1;
+                        target = format!("{key}-{suffix}");
+                    }
+                }
+            }
+            if target != key {
+                warnings.push(format!(
+                    "`{key}` from {origin} clashes with the one from {}, hoisting it as `{target}` instead",
+                    self.origins[key]
+                ));
+            }
+            if !self.hoisted.contains_key(target.as_str()) {
+                self.hoisted.insert(target.clone().into(), value);
+                self.origins.insert(target, origin.to_string());
+            }
+        }
+        warnings
+    }
+
+    pub fn into_mapping(self) -> serde_yaml::Mapping {
+        self.hoisted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    const FIRST: &str = r#"
+x-logging: &logging
+  driver: json-file
+x-base: &base
+  restart: always
+  logging: *logging
+x-unused: &unused
+  image: unused
+services:
+  # The api.
+  api:
+    <<: *base
+    image: api
+  other:
+    <<: *unused
+"#;
+
+    const SECOND: &str = r#"
+x-logging: &logging
+  driver: syslog
+services:
+  worker:
+    image: worker
+    logging: *logging
+"#;
+
+    fn parse(text: &str) -> DockerComposeFile {
+        DockerComposeFile::try_from(&Bytes::from(text.to_string())).unwrap()
+    }
+
+    #[test]
+    fn test_top_level_anchors_and_blocks() {
+        assert_eq!(
+            top_level_anchors(FIRST),
+            vec![
+                ("x-logging".to_string(), "logging".to_string()),
+                ("x-base".to_string(), "base".to_string()),
+                ("x-unused".to_string(), "unused".to_string()),
+            ]
+        );
+        assert_eq!(
+            service_block(FIRST, "api").unwrap(),
+            "  api:\n    <<: *base\n    image: api"
+        );
+        assert_eq!(
+            top_level_block(FIRST, "x-logging").unwrap(),
+            "x-logging: &logging\n  driver: json-file"
+        );
+    }
+
+    #[test]
+    fn test_referenced_extensions_is_transitive() {
+        assert_eq!(
+            referenced_extensions(FIRST, &["api".to_string()]),
+            vec!["x-logging", "x-base"]
+        );
+    }
+
+    #[test]
+    fn test_compose_file_referenced_extensions() {
+        let file = parse(FIRST);
+        let api = file.get_service("api").unwrap();
+        assert_eq!(api["restart"], "always");
+        assert_eq!(api["logging"]["driver"], "json-file");
+
+        let extensions = file.referenced_extensions(&["api".to_string()]);
+        let keys: Vec<_> = extensions.keys().filter_map(|key| key.as_str()).collect();
+        assert_eq!(keys, vec!["x-logging", "x-base"]);
+        assert_eq!(extensions["x-base"]["logging"]["driver"], "json-file");
+
+        let unparsed = DockerComposeFile { raw: None, ..file };
+        assert!(
+            unparsed
+                .referenced_extensions(&["api".to_string()])
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_hoist_renames_clashing_anchors() {
+        let mut hoister = AnchorHoister::new();
+
+        let warnings = hoister.hoist(&parse(FIRST), &["api".to_string()], "first");
+        assert!(warnings.is_empty());
+        let warnings = hoister.hoist(&parse(SECOND), &["worker".to_string()], "second");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("x-logging-2"), "{}", warnings[0]);
+
+        let hoisted = hoister.into_mapping();
+        let keys: Vec<_> = hoisted.keys().filter_map(|key| key.as_str()).collect();
+        assert_eq!(keys, vec!["x-logging", "x-base", "x-logging-2"]);
+        assert_eq!(hoisted["x-logging"]["driver"], "json-file");
+        assert_eq!(hoisted["x-logging-2"]["driver"], "syslog");
+    }
+
+    #[test]
+    fn test_hoist_dedupes_identical_anchors() {
+        let mut hoister = AnchorHoister::new();
+        hoister.hoist(&parse(SECOND), &["worker".to_string()], "first");
+        let warnings = hoister.hoist(&parse(SECOND), &["worker".to_string()], "second");
+
+        assert!(warnings.is_empty());
+        assert_eq!(hoister.into_mapping().len(), 1);
+    }
+}
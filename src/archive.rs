@@ -0,0 +1,171 @@
+use crate::{DEFAULT_MAX_FILE_SIZE, DownloadFile, YammerError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::io::Read;
+
+/// A file inside a zip archive that's downloaded from a url, like a CI artifact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipFileSpec {
+    pub url: String,
+    /// The path of the file within the archive.
+    pub entry: String,
+}
+
+impl ZipFileSpec {
+    pub fn new(url: impl Into<String>, entry: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            entry: entry.into(),
+        }
+    }
+}
+
+/// Downloads zip archives and extracts a single entry out of them.
+#[derive(Debug, Clone)]
+pub struct ZipFileDownloader {
+    pub client: reqwest::Client,
+    /// The most bytes a downloaded archive may have.
+    pub max_file_size: u64,
+}
+
+impl ZipFileDownloader {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+}
+
+impl Default for ZipFileDownloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Read one entry out of the bytes of a zip archive, failing with [`YammerError::TooLarge`]
+/// once more than `limit` bytes have been extracted, whatever size the archive claims it has.
+pub fn extract_zip_entry(
+    archive: &[u8],
+    spec: &ZipFileSpec,
+    limit: u64,
+) -> Result<Bytes, YammerError> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(archive))?;
+    let mut entry = match archive.by_name(&spec.entry) {
+        Ok(entry) => entry,
+        Err(zip::result::ZipError::FileNotFound) => {
+            return Err(YammerError::MissingZipEntry {
+                url: spec.url.clone(),
+                entry: spec.entry.clone(),
+            });
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let mut contents = Vec::new();
+    (&mut entry)
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut contents)
+        .map_err(zip::result::ZipError::from)?;
+    if contents.len() as u64 > limit {
+        return Err(YammerError::TooLarge {
+            url: format!("{}!{}", spec.url, spec.entry),
+            limit,
+        });
+    }
+    Ok(contents.into())
+}
+
+#[async_trait]
+impl DownloadFile for ZipFileDownloader {
+    type FileSpec = ZipFileSpec;
+
+    async fn download_file(&self, spec: &Self::FileSpec) -> Result<Bytes, YammerError> {
+        let response = self
+            .client
+            .get(&spec.url)
+            .header(reqwest::header::USER_AGENT, crate::USER_AGENT)
+            .send()
+            .await?;
+        let response = crate::refusal(&spec.url, response)
+            .await?
+            .error_for_status()?;
+        let archive = crate::read_body(&spec.url, response, self.max_file_size).await?;
+        extract_zip_entry(&archive, spec, self.max_file_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer};
+    use std::io::Write;
+
+    fn zip_archive(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, contents) in entries {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_download_file_from_zip() {
+        let archive = zip_archive(&[
+            ("README.md", "hi"),
+            (
+                "deploy/docker-compose.yml",
+                "services:\n  redis:\n    image: redis\n",
+            ),
+        ]);
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/artifacts/compose.zip" => MockResponse::ok(archive.clone()),
+            _ => MockResponse::new(404, "not found"),
+        })
+        .await;
+        let url = format!("{}/artifacts/compose.zip", server.url());
+        let downloader = ZipFileDownloader::new();
+
+        let compose_file = downloader
+            .download_compose_file(&ZipFileSpec::new(&url, "deploy/docker-compose.yml"))
+            .await
+            .unwrap();
+        assert_eq!(compose_file.get_service("redis").unwrap()["image"], "redis");
+
+        let missing = downloader
+            .download_file(&ZipFileSpec::new(&url, "docker-compose.yml"))
+            .await;
+        assert!(
+            matches!(missing, Err(YammerError::MissingZipEntry { .. })),
+            "{missing:?}"
+        );
+    }
+
+    #[test]
+    fn test_extracted_entry_is_capped() {
+        let archive = zip_archive(&[("docker-compose.yml", &"a".repeat(1024))]);
+        let spec = ZipFileSpec::new("https://example.com/a.zip", "docker-compose.yml");
+
+        assert_eq!(
+            extract_zip_entry(&archive, &spec, 1024).unwrap().len(),
+            1024
+        );
+        let err = extract_zip_entry(&archive, &spec, 1023).unwrap_err();
+        assert!(
+            matches!(&err, YammerError::TooLarge { url, limit: 1023 } if url == "https://example.com/a.zip!docker-compose.yml"),
+            "{err:?}"
+        );
+    }
+}
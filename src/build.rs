@@ -0,0 +1,169 @@
+use crate::{DownloadFile, GithubFileSpec, YammerError};
+use serde_yaml::Value;
+
+/// Fetch the Dockerfile a service builds from and inline it as `build.dockerfile_inline`.
+///
+/// The build context is resolved relative to the compose file described by `spec`, so the
+/// Dockerfile is downloaded from the same repository and branch. A short-form `build: ./dir`
+/// is expanded into a `build.context` mapping first. Returns whether anything was inlined,
+/// which is not the case for services without a build, or with a remote build context.
+pub async fn inline_dockerfile<D>(
+    downloader: &D,
+    spec: &GithubFileSpec<String>,
+    service: &mut serde_yaml::Mapping,
+) -> Result<bool, YammerError>
+where
+    D: DownloadFile<FileSpec = GithubFileSpec<String>> + Sync + ?Sized,
+{
+    let Some(build) = service.get_mut("build") else {
+        return Ok(false);
+    };
+    if let Value::String(context) = build {
+        let mut expanded = serde_yaml::Mapping::new();
+        expanded.insert("context".into(), context.clone().into());
+        *build = Value::Mapping(expanded);
+    }
+    let Some(build) = build.as_mapping_mut() else {
+        return Ok(false);
+    };
+    let Some(context) = build.get("context").and_then(Value::as_str) else {
+        return Ok(false);
+    };
+    if context.contains("://") || context.starts_with('/') {
+        return Ok(false);
+    }
+    let dockerfile = build
+        .get("dockerfile")
+        .and_then(Value::as_str)
+        .unwrap_or("Dockerfile");
+    let dockerfile_spec = spec.resolve_relative(&format!("{context}/{dockerfile}"));
+
+    let contents = downloader.download_file(&dockerfile_spec).await?;
+    let contents = String::from_utf8_lossy(&contents).into_owned();
+
+    // `dockerfile` and `dockerfile_inline` are mutually exclusive.
+    build.remove("dockerfile");
+    build.insert("dockerfile_inline".into(), contents.into());
+    Ok(true)
+}
+
+/// Drop a service's `build`, so it only runs its `image`. Returns `false` if the service had a
+/// `build` but no image, which leaves it with nothing to run.
+pub fn strip_build(service: &mut serde_yaml::Mapping) -> bool {
+    service.remove("build").is_none() || service.contains_key("image")
+}
+
+/// Put `prefix` in front of a service's relative build context, e.g. to point it at where the
+/// source repository is checked out. Remote and absolute contexts are left alone.
+pub fn prefix_build_context(service: &mut serde_yaml::Mapping, prefix: &str) {
+    let context = match service.get_mut("build") {
+        Some(Value::String(context)) => context,
+        Some(Value::Mapping(build)) => match build.get_mut("context") {
+            Some(Value::String(context)) => context,
+            _ => return,
+        },
+        _ => return,
+    };
+    if context.contains("://") || context.starts_with('/') {
+        return;
+    }
+    let prefix = prefix.trim_end_matches('/');
+    *context = match context.trim_start_matches("./") {
+        "" | "." => prefix.to_string(),
+        relative => format!("{prefix}/{relative}"),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GithubFileDownloader;
+    use crate::test_util::{MockResponse, MockServer, github_spec};
+
+    #[tokio::test]
+    async fn test_inline_dockerfile() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/org/repo/refs/heads/main/docker/postgres/Dockerfile.dev" => {
+                MockResponse::ok("FROM postgres:16\n")
+            }
+            _ => MockResponse::new(404, "not found"),
+        })
+        .await;
+        let downloader = GithubFileDownloader::new().with_raw_host(server.url());
+        let spec = github_spec("repo", "docker-compose.yml");
+
+        let mut service: serde_yaml::Mapping = serde_yaml::from_str(
+            r#"
+            image: postgres
+            build:
+              context: ./docker/postgres
+              dockerfile: Dockerfile.dev"#,
+        )
+        .unwrap();
+
+        let inlined = inline_dockerfile(&downloader, &spec, &mut service)
+            .await
+            .unwrap();
+        assert!(inlined);
+
+        let build = service["build"].as_mapping().unwrap();
+        assert_eq!(build["dockerfile_inline"], "FROM postgres:16\n");
+        assert_eq!(build["context"], "./docker/postgres");
+        assert!(!build.contains_key("dockerfile"));
+    }
+
+    #[test]
+    fn test_strip_build() {
+        let mut service: serde_yaml::Mapping =
+            serde_yaml::from_str("image: postgres\nbuild: ./docker/postgres").unwrap();
+        assert!(strip_build(&mut service));
+        assert!(!service.contains_key("build"));
+        assert_eq!(service["image"], "postgres");
+
+        let mut service: serde_yaml::Mapping =
+            serde_yaml::from_str("build: ./docker/postgres").unwrap();
+        assert!(!strip_build(&mut service));
+
+        let mut service: serde_yaml::Mapping = serde_yaml::from_str("extends: base").unwrap();
+        assert!(strip_build(&mut service));
+    }
+
+    #[test]
+    fn test_prefix_build_context() {
+        let prefixed = |yaml: &str| {
+            let mut service: serde_yaml::Mapping = serde_yaml::from_str(yaml).unwrap();
+            prefix_build_context(&mut service, "vendor/omnivore/");
+            service
+        };
+        assert_eq!(
+            prefixed("build: ./docker/postgres")["build"],
+            "vendor/omnivore/docker/postgres"
+        );
+        assert_eq!(
+            prefixed("build:\n  context: .\n  dockerfile: Dockerfile.dev")["build"]["context"],
+            "vendor/omnivore"
+        );
+        assert_eq!(
+            prefixed("build: ../shared")["build"],
+            "vendor/omnivore/../shared"
+        );
+        assert_eq!(
+            prefixed("build: https://github.com/org/repo.git")["build"],
+            "https://github.com/org/repo.git"
+        );
+        assert_eq!(prefixed("build: /srv/app")["build"], "/srv/app");
+        assert!(!prefixed("image: redis").contains_key("build"));
+    }
+
+    #[tokio::test]
+    async fn test_inline_dockerfile_without_build() {
+        let downloader = GithubFileDownloader::new().with_raw_host("http://127.0.0.1:9");
+        let spec = github_spec("repo", "docker-compose.yml");
+        let mut service: serde_yaml::Mapping = serde_yaml::from_str("image: redis").unwrap();
+
+        let inlined = inline_dockerfile(&downloader, &spec, &mut service)
+            .await
+            .unwrap();
+        assert!(!inlined);
+    }
+}
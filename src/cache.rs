@@ -0,0 +1,391 @@
+use crate::{DownloadFile, GithubFileSpec, GithubRef, YammerError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use fs2::FileExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// How long cached files at a branch or tag are used before they're downloaded again.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// A file spec that can be cached, under a key that uniquely identifies the file it downloads.
+pub trait CacheKey {
+    fn cache_key(&self) -> String;
+
+    /// Whether the file can never change, like a file at a commit SHA, so its cached copy
+    /// never expires.
+    fn is_immutable(&self) -> bool {
+        false
+    }
+
+    /// Whether the file is worth caching at all. Files that are already on disk aren't.
+    fn is_cacheable(&self) -> bool {
+        true
+    }
+}
+
+impl<S: AsRef<str>> CacheKey for GithubFileSpec<S> {
+    fn cache_key(&self) -> String {
+        self.get_url()
+    }
+
+    fn is_immutable(&self) -> bool {
+        self.reference == GithubRef::Commit
+    }
+}
+
+/// Whether a git ref is a full commit SHA rather than a branch or tag.
+pub fn is_commit_sha(reference: &str) -> bool {
+    reference.len() == 40 && reference.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// `$XDG_CACHE_HOME/yammer`, falling back to `~/.cache/yammer`.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_home.join("yammer"))
+}
+
+/// Whether a download was served from the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+}
+
+impl std::fmt::Display for CacheStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheStatus::Hit => write!(f, "hit"),
+            CacheStatus::Miss => write!(f, "miss"),
+        }
+    }
+}
+
+/// Serves downloads from an on-disk cache, only delegating to the wrapped downloader on a miss.
+///
+/// Cached files expire after the TTL, if one is set, except for [immutable] ones.
+///
+/// [immutable]: CacheKey::is_immutable
+///
+/// Failing to read or write the cache is reported as [`YammerError::Cache`], never as a
+/// download error.
+#[derive(Debug, Clone)]
+pub struct CachingDownloader<D> {
+    pub inner: D,
+    pub dir: PathBuf,
+    /// How long cached files are used for, forever if `None`.
+    pub ttl: Option<Duration>,
+    /// How the latest download of each cache key went.
+    statuses: Arc<Mutex<HashMap<String, CacheStatus>>>,
+}
+
+impl<D> CachingDownloader<D> {
+    pub fn new(inner: D, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            dir: dir.into(),
+            ttl: None,
+            statuses: Default::default(),
+        }
+    }
+
+    /// Download files again once their cached copy is older than `ttl`.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    fn record(&self, key: String, status: CacheStatus) {
+        let mut statuses = self.statuses.lock().expect("cache statuses lock poisoned");
+        statuses.insert(key, status);
+    }
+
+    /// Where the contents for a cache key are stored: a file named by the key's SHA256, so
+    /// different keys never share an entry.
+    pub fn path_for(&self, key: &str) -> PathBuf {
+        let digest = Sha256::digest(key.as_bytes());
+        let file_name: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+        self.dir.join(file_name)
+    }
+}
+
+impl<D> CachingDownloader<D>
+where
+    D: DownloadFile,
+    D::FileSpec: CacheKey,
+{
+    /// The key `spec` is cached under: its own key along with the url the wrapped downloader
+    /// fetches it from, so the same file on different hosts is cached separately.
+    pub fn key_for(&self, spec: &D::FileSpec) -> String {
+        match self.inner.source_url(spec) {
+            Some(url) => format!("{} {url}", spec.cache_key()),
+            None => spec.cache_key(),
+        }
+    }
+
+    /// Whether the latest download of `spec` was served from the cache, if it was downloaded.
+    pub fn cache_status(&self, spec: &D::FileSpec) -> Option<CacheStatus> {
+        let statuses = self.statuses.lock().expect("cache statuses lock poisoned");
+        statuses.get(&self.key_for(spec)).copied()
+    }
+}
+
+/// Take an advisory lock on the lock file next to a cache entry, so processes sharing the
+/// cache directory don't read an entry while another one is writing it. The lock is released
+/// when the returned file is dropped.
+fn lock_entry(path: &Path, exclusive: bool) -> std::io::Result<File> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path)?;
+    if exclusive {
+        FileExt::lock_exclusive(&lock)?;
+    } else {
+        FileExt::lock_shared(&lock)?;
+    }
+    Ok(lock)
+}
+
+/// Whether the cache entry at `path` was written `ttl` or longer ago. Entries whose age can't
+/// be told count as expired.
+fn is_expired(path: &Path, ttl: Duration) -> bool {
+    match std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+        Ok(written) => SystemTime::now()
+            .duration_since(written)
+            .is_ok_and(|age| age >= ttl),
+        Err(err) => err.kind() != std::io::ErrorKind::NotFound,
+    }
+}
+
+fn read_cached(path: &Path) -> Result<Option<Bytes>, YammerError> {
+    let _lock = match lock_entry(path, false) {
+        Ok(lock) => lock,
+        Err(err)
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
+            return Ok(None);
+        }
+        Err(err) => return Err(YammerError::Cache(err)),
+    };
+    match std::fs::read(path) {
+        Ok(contents) => Ok(Some(contents.into())),
+        Err(err)
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(YammerError::Cache(err)),
+    }
+}
+
+/// Write a cache entry under an exclusive lock, through a temporary file that's renamed into
+/// place so the entry is never seen half-written.
+fn write_cached(dir: &Path, path: &Path, contents: &[u8]) -> Result<(), YammerError> {
+    std::fs::create_dir_all(dir).map_err(YammerError::Cache)?;
+    let _lock = lock_entry(path, true).map_err(YammerError::Cache)?;
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    std::fs::write(&tmp_path, contents).map_err(YammerError::Cache)?;
+    std::fs::rename(&tmp_path, path).map_err(YammerError::Cache)
+}
+
+#[async_trait]
+impl<D> DownloadFile for CachingDownloader<D>
+where
+    D: DownloadFile + Send + Sync,
+    D::FileSpec: CacheKey,
+{
+    type FileSpec = D::FileSpec;
+
+    fn source_url(&self, spec: &Self::FileSpec) -> Option<String> {
+        self.inner.source_url(spec)
+    }
+
+    async fn download_file(&self, spec: &Self::FileSpec) -> Result<Bytes, YammerError> {
+        if !spec.is_cacheable() {
+            return self.inner.download_file(spec).await;
+        }
+        let key = self.key_for(spec);
+        let path = self.path_for(&key);
+        let expired = self
+            .ttl
+            .is_some_and(|ttl| !spec.is_immutable() && is_expired(&path, ttl));
+        if !expired && let Some(contents) = read_cached(&path)? {
+            self.record(key, CacheStatus::Hit);
+            return Ok(contents);
+        }
+        let contents = self.inner.download_file(spec).await?;
+        write_cached(&self.dir, &path, &contents)?;
+        self.record(key, CacheStatus::Miss);
+        Ok(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GithubFileDownloader;
+    use crate::test_util::{MockResponse, MockServer, TempDir, github_spec};
+
+    fn spec() -> GithubFileSpec<String> {
+        github_spec("repo", "docker-compose.yml")
+    }
+
+    #[tokio::test]
+    async fn test_caching_downloader_hits_cache() {
+        let server = MockServer::start(|_| MockResponse::ok("services: {}")).await;
+        let dir = TempDir::new("cache-hit");
+        let downloader = CachingDownloader::new(
+            GithubFileDownloader::new().with_raw_host(server.url()),
+            dir.path(),
+        );
+
+        assert_eq!(downloader.cache_status(&spec()), None);
+        let first = downloader.download_file(&spec()).await.unwrap();
+        assert_eq!(downloader.cache_status(&spec()), Some(CacheStatus::Miss));
+        let second = downloader.download_file(&spec()).await.unwrap();
+        assert_eq!(downloader.cache_status(&spec()), Some(CacheStatus::Hit));
+
+        assert_eq!(first, second);
+        assert_eq!(server.total_hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_similar_keys_and_other_hosts_get_their_own_entries() {
+        let server = MockServer::start(|request| MockResponse::ok(request.path.clone())).await;
+        let other = MockServer::start(|_| MockResponse::ok("other host")).await;
+        let dir = TempDir::new("cache-keys");
+        let downloader = CachingDownloader::new(
+            GithubFileDownloader::new().with_raw_host(server.url()),
+            dir.path(),
+        );
+        let on_other_host = CachingDownloader::new(
+            GithubFileDownloader::new().with_raw_host(other.url()),
+            dir.path(),
+        );
+        let branch = |branch: &str| {
+            GithubFileSpec::new(
+                "org".to_string(),
+                "repo".to_string(),
+                branch.to_string(),
+                "docker-compose.yml".to_string(),
+            )
+        };
+
+        let slash = downloader.download_file(&branch("feat/x")).await.unwrap();
+        let underscore = downloader.download_file(&branch("feat_x")).await.unwrap();
+        assert_ne!(slash, underscore);
+        assert_eq!(
+            downloader.cache_status(&branch("feat_x")),
+            Some(CacheStatus::Miss)
+        );
+        downloader.download_file(&spec()).await.unwrap();
+        let elsewhere = on_other_host.download_file(&spec()).await.unwrap();
+        assert_eq!(elsewhere, "other host");
+        assert_eq!(on_other_host.cache_status(&spec()), Some(CacheStatus::Miss));
+    }
+
+    #[tokio::test]
+    async fn test_expired_entries_are_downloaded_again_unless_immutable() {
+        let server = MockServer::start(|_| MockResponse::ok("services: {}")).await;
+        let dir = TempDir::new("cache-ttl");
+        let downloader = CachingDownloader::new(
+            GithubFileDownloader::new().with_raw_host(server.url()),
+            dir.path(),
+        )
+        .with_ttl(Duration::ZERO);
+        let pinned = GithubFileSpec::new(
+            "org".to_string(),
+            "repo".to_string(),
+            "0123456789abcdef0123456789abcdef01234567".to_string(),
+            "docker-compose.yml".to_string(),
+        )
+        .with_ref(GithubRef::Commit);
+        assert!(pinned.is_immutable() && !spec().is_immutable());
+
+        for spec in [spec(), pinned.clone(), spec(), pinned] {
+            downloader.download_file(&spec).await.unwrap();
+        }
+
+        assert_eq!(downloader.cache_status(&spec()), Some(CacheStatus::Miss));
+        assert_eq!(
+            server.hits("/org/repo/refs/heads/main/docker-compose.yml"),
+            2
+        );
+        assert_eq!(server.total_hits(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_caching_downloader_write_error_is_cache_error() {
+        let server = MockServer::start(|_| MockResponse::ok("services: {}")).await;
+        // A regular file where the cache directory should be can't be written into,
+        // regardless of which user runs the tests.
+        let dir = TempDir::new("cache-not-a-dir");
+        let not_a_dir = dir.join("cache");
+        std::fs::write(&not_a_dir, "").unwrap();
+        let downloader = CachingDownloader::new(
+            GithubFileDownloader::new().with_raw_host(server.url()),
+            not_a_dir,
+        );
+
+        let result = downloader.download_file(&spec()).await;
+
+        assert!(matches!(result, Err(YammerError::Cache(_))), "{result:?}");
+        assert_eq!(server.total_hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_caching_downloader_download_error_is_not_cache_error() {
+        let server = MockServer::start(|_| MockResponse::new(404, "not found")).await;
+        let dir = TempDir::new("cache-download-error");
+        let downloader = CachingDownloader::new(
+            GithubFileDownloader::new().with_raw_host(server.url()),
+            dir.path(),
+        );
+
+        let result = downloader.download_file(&spec()).await;
+
+        assert!(matches!(result, Err(YammerError::Reqwest(_))), "{result:?}");
+    }
+
+    #[test]
+    fn test_concurrent_writes_leave_a_consistent_entry() {
+        let dir = TempDir::new("cache-lock");
+        let path = dir.join("entry");
+        let contents: Vec<Vec<u8>> = (0..8u8).map(|i| vec![b'a' + i; 256 * 1024]).collect();
+
+        std::thread::scope(|scope| {
+            for contents in &contents {
+                let (dir, path) = (dir.path(), &path);
+                scope.spawn(move || {
+                    for _ in 0..4 {
+                        write_cached(dir, path, contents).unwrap();
+                        let read = read_cached(path).unwrap().unwrap();
+                        assert!(read.len() == 256 * 1024 && read.iter().all(|b| *b == read[0]));
+                    }
+                });
+            }
+        });
+
+        let entry = read_cached(&path).unwrap().unwrap();
+        assert!(contents.iter().any(|contents| entry == contents[..]));
+    }
+}
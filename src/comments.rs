@@ -0,0 +1,131 @@
+use crate::anchors::{indentation, is_blank_or_comment, key_of};
+use std::collections::BTreeMap;
+
+/// The lines of the top-level `services` block, as `(line index, line)` pairs, and the
+/// indentation of the service names in it.
+fn services_section<'a>(lines: &[&'a str]) -> Option<(Vec<(usize, &'a str)>, usize)> {
+    let start = lines
+        .iter()
+        .position(|line| indentation(line) == 0 && key_of(line) == Some("services"))?;
+    let section: Vec<(usize, &str)> = lines[start + 1..]
+        .iter()
+        .copied()
+        .enumerate()
+        .take_while(|(_, line)| is_blank_or_comment(line) || indentation(line) > 0)
+        .map(|(offset, line)| (start + 1 + offset, line))
+        .collect();
+    let indent = section
+        .iter()
+        .find(|(_, line)| !is_blank_or_comment(line))
+        .map(|(_, line)| indentation(line))?;
+    Some((section, indent))
+}
+
+/// The comment lines directly above a service's name under the top-level `services` key,
+/// without their indentation. A blank line ends the comments that belong to the service.
+pub fn service_comments(text: &str, name: &str) -> Vec<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let Some((section, indent)) = services_section(&lines) else {
+        return vec![];
+    };
+    let Some(&(start, _)) = section.iter().find(|(_, line)| {
+        !is_blank_or_comment(line) && indentation(line) == indent && key_of(line) == Some(name)
+    }) else {
+        return vec![];
+    };
+    let mut comments: Vec<String> = lines[..start]
+        .iter()
+        .rev()
+        .map(|line| line.trim_start())
+        .take_while(|line| line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    comments.reverse();
+    comments
+}
+
+/// Put the comments of each service back above its name in serialized YAML, indented like
+/// the name. See [`service_comments`].
+pub fn insert_service_comments(yaml: &str, comments: &BTreeMap<String, Vec<String>>) -> String {
+    let lines: Vec<&str> = yaml.lines().collect();
+    let Some((section, indent)) = services_section(&lines) else {
+        return yaml.to_string();
+    };
+    let mut commented: BTreeMap<usize, &Vec<String>> = BTreeMap::new();
+    for (index, line) in section {
+        if indentation(line) == indent
+            && let Some(comments) = key_of(line).and_then(|name| comments.get(name))
+        {
+            commented.insert(index, comments);
+        }
+    }
+
+    let mut output = String::with_capacity(yaml.len());
+    for (index, line) in lines.iter().enumerate() {
+        for comment in commented
+            .get(&index)
+            .into_iter()
+            .flat_map(|comments| comments.iter())
+        {
+            output.push_str(&" ".repeat(indent));
+            output.push_str(comment);
+            output.push('\n');
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPOSE: &str = r#"
+services:
+  # The api.
+  # Serves the frontend.
+  api:
+    image: api
+    # Not a service comment.
+    restart: always
+
+  # Separated by a blank line, so it belongs to db.
+
+  db:
+    image: postgres
+  # Redis, for caching.
+  "redis":
+    image: redis
+"#;
+
+    #[test]
+    fn test_service_comments() {
+        assert_eq!(
+            service_comments(COMPOSE, "api"),
+            vec!["# The api.", "# Serves the frontend."]
+        );
+        assert!(service_comments(COMPOSE, "db").is_empty());
+        assert_eq!(
+            service_comments(COMPOSE, "redis"),
+            vec!["# Redis, for caching."]
+        );
+        assert!(service_comments(COMPOSE, "missing").is_empty());
+        assert!(service_comments("x-api: {}\n", "api").is_empty());
+    }
+
+    #[test]
+    fn test_insert_service_comments() {
+        let comments = BTreeMap::from([
+            ("api".to_string(), service_comments(COMPOSE, "api")),
+            ("redis".to_string(), service_comments(COMPOSE, "redis")),
+        ]);
+        let yaml = "services:\n  api:\n    image: api\n  db:\n    image: postgres\n  redis:\n    image: redis\nvolumes: {}\n";
+
+        assert_eq!(
+            insert_service_comments(yaml, &comments),
+            "services:\n  # The api.\n  # Serves the frontend.\n  api:\n    image: api\n  db:\n    image: postgres\n  # Redis, for caching.\n  redis:\n    image: redis\nvolumes: {}\n"
+        );
+        assert_eq!(insert_service_comments("{}\n", &comments), "{}\n");
+    }
+}
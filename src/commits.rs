@@ -0,0 +1,237 @@
+use crate::{
+    BearerToken, GITHUB_API_BASE, GithubFileDownloader, GithubFileSpec, GithubRef, YammerError,
+};
+use serde::Deserialize;
+use std::time::SystemTime;
+
+#[derive(Debug, Deserialize)]
+struct Commit {
+    commit: CommitDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitDetails {
+    committer: Signature,
+}
+
+#[derive(Debug, Deserialize)]
+struct Signature {
+    date: String,
+}
+
+/// Looks up when files on GitHub were last changed, through the commits API.
+#[derive(Debug, Clone)]
+pub struct CommitDates {
+    pub client: reqwest::Client,
+    pub api_base: String,
+    /// The most bytes an API response may have.
+    pub max_file_size: u64,
+    /// A token to authenticate with, e.g. for private repositories.
+    pub token: Option<BearerToken>,
+}
+
+impl CommitDates {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_base: GITHUB_API_BASE.to_string(),
+            max_file_size: crate::DEFAULT_MAX_FILE_SIZE,
+            token: None,
+        }
+    }
+
+    /// Look up commit dates through the API, client, token and size limit `github` downloads
+    /// files with, e.g. those of a GitHub Enterprise host.
+    pub fn for_downloader(github: &GithubFileDownloader) -> Self {
+        Self {
+            client: github.client.clone(),
+            api_base: github.api_base.clone(),
+            max_file_size: github.max_file_size,
+            token: github.token.clone(),
+        }
+    }
+
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn with_api_base(mut self, api_base: impl Into<String>) -> Self {
+        self.api_base = api_base.into();
+        self
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Send `token` as an `Authorization: Bearer` header with every request.
+    pub fn with_token(mut self, token: BearerToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// The commit date of the latest commit on the spec's branch that touched its file, or
+    /// `None` if no commit did.
+    pub async fn last_modified(
+        &self,
+        spec: &GithubFileSpec<String>,
+    ) -> Result<Option<SystemTime>, YammerError> {
+        let url = format!(
+            "{}/repos/{}/{}/commits",
+            self.api_base.trim_end_matches('/'),
+            spec.project,
+            spec.repository
+        );
+        let mut query = vec![("path", spec.filepath.as_str())];
+        // Without a `sha`, the API lists the commits on the default branch.
+        if spec.reference != GithubRef::DefaultBranch {
+            query.push(("sha", spec.branch.as_str()));
+        }
+        query.push(("per_page", "1"));
+        let mut request = self
+            .client
+            .get(&url)
+            .query(&query)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .header(reqwest::header::USER_AGENT, crate::USER_AGENT);
+        if let Some(token) = &self.token {
+            request = token.apply(request);
+        }
+        let response = crate::github_refusal(&url, request.send().await?, self.token.as_ref())
+            .await?
+            .error_for_status()?;
+        let commits: Vec<Commit> =
+            serde_json::from_slice(&crate::read_body(&url, response, self.max_file_size).await?)?;
+        let Some(commit) = commits.first() else {
+            return Ok(None);
+        };
+        let date = &commit.commit.committer.date;
+        humantime::parse_rfc3339(date)
+            .map(Some)
+            .map_err(|_| YammerError::InvalidCommitDate(date.clone()))
+    }
+}
+
+impl Default for CommitDates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer, github_spec};
+    use crate::{ComposeServiceSpec, ConflictPolicy, FileSource, ServiceMerger};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_last_modified() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/repos/org/repo/commits?path=docker-compose.yml&sha=main&per_page=1" => {
+                MockResponse::ok(
+                    r#"[{"sha": "abc", "commit": {"committer": {"date": "2024-01-02T03:04:05Z"}}}]"#,
+                )
+            }
+            "/repos/org/repo/commits?path=new.yml&sha=main&per_page=1" => MockResponse::ok("[]"),
+            _ => MockResponse::new(404, "{}"),
+        })
+        .await;
+        let dates = CommitDates::new().with_api_base(server.url());
+        let spec = |path: &str| github_spec("repo", path);
+
+        assert_eq!(
+            dates
+                .last_modified(&spec("docker-compose.yml"))
+                .await
+                .unwrap(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_704_164_645))
+        );
+        assert_eq!(dates.last_modified(&spec("new.yml")).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_last_modified_caps_the_response() {
+        let server = MockServer::start(|_| {
+            MockResponse::ok(
+                r#"[{"sha": "abc", "commit": {"committer": {"date": "2024-01-02T03:04:05Z"}}}]"#,
+            )
+        })
+        .await;
+        let dates = CommitDates::new()
+            .with_api_base(server.url())
+            .with_max_file_size(8);
+
+        assert!(matches!(
+            dates
+                .last_modified(&github_spec("repo", "docker-compose.yml"))
+                .await,
+            Err(YammerError::TooLarge { limit: 8, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_last_modified_sends_the_token() {
+        let server = MockServer::start(|request| {
+            match request.headers.get("authorization").map(String::as_str) {
+                Some("Bearer ghp_s3cret") => MockResponse::ok("[]"),
+                _ => MockResponse::new(404, "{}"),
+            }
+        })
+        .await;
+        let spec = github_spec("private", "docker-compose.yml");
+
+        let dates = CommitDates::new().with_api_base(server.url());
+        assert!(dates.last_modified(&spec).await.is_err());
+        let dates = dates.with_token(BearerToken::new("ghp_s3cret"));
+        assert_eq!(dates.last_modified(&spec).await.unwrap(), None);
+
+        let github = GithubFileDownloader::new()
+            .with_api_base(server.url())
+            .with_token(BearerToken::new("ghp_s3cret"));
+        let dates = CommitDates::for_downloader(&github);
+        assert_eq!(dates.api_base, server.url());
+        assert_eq!(dates.last_modified(&spec).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_newest_source_wins_conflict() {
+        let server = MockServer::start(|request| {
+            let date = match request.path.as_str() {
+                path if path.starts_with("/repos/org/old/") => "2023-05-01T00:00:00Z",
+                path if path.starts_with("/repos/org/new/") => "2024-05-01T00:00:00Z",
+                _ => return MockResponse::new(404, "{}"),
+            };
+            MockResponse::ok(format!(
+                r#"[{{"commit": {{"committer": {{"date": "{date}"}}}}}}]"#
+            ))
+        })
+        .await;
+        let dates = CommitDates::new().with_api_base(server.url());
+        let mut merger = ServiceMerger::default().with_on_conflict(ConflictPolicy::Newest);
+
+        // The newer source is listed first, so it would lose to the older one by default.
+        for (index, spec) in [
+            "org/new:docker-compose.yml@redis",
+            "org/old:docker-compose.yml@redis",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let spec: ComposeServiceSpec = spec.parse().unwrap();
+            let FileSource::Github(source) = &spec.source else {
+                unreachable!()
+            };
+            let modified = dates.last_modified(source).await.unwrap();
+            let service = format!("image: redis:{}", source.repository);
+            let service = serde_yaml::from_str(&service).unwrap();
+            merger
+                .add_modified(&spec.id(index), "redis", service, modified)
+                .unwrap();
+        }
+
+        assert_eq!(merger.services()["redis"]["image"], "redis:new");
+    }
+}
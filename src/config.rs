@@ -0,0 +1,115 @@
+use crate::YammerError;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The forge host files are downloaded from unless configured otherwise.
+pub const DEFAULT_FORGE_HOST: &str = "github.com";
+
+/// Where a forge serves raw file contents and its API from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct HostConfig {
+    pub raw_host: Option<String>,
+    pub api_base: Option<String>,
+}
+
+/// Settings read from `config.toml`, so they don't have to be repeated as flags, e.g.
+///
+/// ```toml
+/// host = "github.example.com"
+///
+/// [hosts."github.example.com"]
+/// raw_host = "https://github.example.com/raw"
+/// api_base = "https://github.example.com/api/v3"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Config {
+    /// The forge host to download from when none is given on the command line.
+    pub host: Option<String>,
+    /// The raw-content host and API base of each forge host.
+    #[serde(default)]
+    pub hosts: BTreeMap<String, HostConfig>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/yammer/config.toml`, falling back to `~/.config/yammer/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_home.join("yammer").join("config.toml"))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, YammerError> {
+        toml::from_str(contents).map_err(|err| YammerError::InvalidConfig(err.to_string()))
+    }
+
+    /// Read the config at `path`. A missing file is the same as an empty one.
+    pub fn load(path: &Path) -> Result<Self, YammerError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(source) => Err(YammerError::Io {
+                path: path.to_path_buf(),
+                source,
+            }),
+        }
+    }
+
+    /// The settings for a forge host, or for the configured `host` (or github.com) if `host`
+    /// is `None`.
+    pub fn host_config(&self, host: Option<&str>) -> HostConfig {
+        let host = host.or(self.host.as_deref()).unwrap_or(DEFAULT_FORGE_HOST);
+        self.hosts.get(host).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_maps_enterprise_host() {
+        let config = Config::parse(
+            r#"
+            host = "github.example.com"
+
+            [hosts."github.example.com"]
+            raw_host = "https://github.example.com/raw"
+            api_base = "https://github.example.com/api/v3"
+
+            [hosts."mirror.example.com"]
+            raw_host = "https://mirror.example.com"
+            "#,
+        )
+        .unwrap();
+
+        let enterprise = config.host_config(None);
+        assert_eq!(
+            enterprise.raw_host.as_deref(),
+            Some("https://github.example.com/raw")
+        );
+        assert_eq!(
+            enterprise.api_base.as_deref(),
+            Some("https://github.example.com/api/v3")
+        );
+        let mirror = config.host_config(Some("mirror.example.com"));
+        assert_eq!(
+            mirror.raw_host.as_deref(),
+            Some("https://mirror.example.com")
+        );
+        assert_eq!(mirror.api_base, None);
+        assert_eq!(
+            config.host_config(Some("github.com")),
+            HostConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_load_missing_config_is_empty() {
+        let path = std::env::temp_dir().join("dcompose-no-such-config.toml");
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+        assert!(Config::parse("hosts = 1").is_err());
+    }
+}
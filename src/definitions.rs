@@ -0,0 +1,235 @@
+use crate::DockerComposeFile;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+
+/// A top-level section of named definitions that services refer to by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TopLevelSection {
+    Networks,
+    Volumes,
+    Secrets,
+    Configs,
+}
+
+impl TopLevelSection {
+    pub const ALL: [TopLevelSection; 4] = [
+        TopLevelSection::Networks,
+        TopLevelSection::Volumes,
+        TopLevelSection::Secrets,
+        TopLevelSection::Configs,
+    ];
+
+    /// The top-level key of the section, which is also the key services use to refer to it.
+    pub fn key(&self) -> &'static str {
+        match self {
+            TopLevelSection::Networks => "networks",
+            TopLevelSection::Volumes => "volumes",
+            TopLevelSection::Secrets => "secrets",
+            TopLevelSection::Configs => "configs",
+        }
+    }
+}
+
+impl DockerComposeFile {
+    /// The definitions in one of the file's top-level sections, if it has that section.
+    pub fn definitions(&self, section: TopLevelSection) -> Option<&Mapping> {
+        self.other.get(section.key()).and_then(Value::as_mapping)
+    }
+}
+
+/// The names of the definitions in a top-level section that a service refers to.
+///
+/// Volumes are referred to by the source of a mount, so bind mounts of host paths
+/// (`./data:/data`) and anonymous volumes (`/data`) don't count.
+pub fn service_references(service: &Mapping, section: TopLevelSection) -> Vec<String> {
+    let Some(references) = service.get(section.key()) else {
+        return vec![];
+    };
+    let names: Vec<&str> = match (section, references) {
+        (TopLevelSection::Networks, Value::Mapping(networks)) => {
+            networks.keys().filter_map(Value::as_str).collect()
+        }
+        (TopLevelSection::Volumes, Value::Sequence(mounts)) => mounts
+            .iter()
+            .filter_map(|mount| match mount {
+                Value::String(mount) => mount
+                    .split_once(':')
+                    .map(|(source, _)| source)
+                    .filter(|source| !source.starts_with(['.', '/', '~', '$'])),
+                Value::Mapping(mount) if mount.get("type").is_none_or(|kind| kind == "volume") => {
+                    mount.get("source").and_then(Value::as_str)
+                }
+                _ => None,
+            })
+            .collect(),
+        (_, Value::Sequence(entries)) => entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Value::String(name) => Some(name.as_str()),
+                Value::Mapping(entry) => entry.get("source").and_then(Value::as_str),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+    names.into_iter().map(str::to_string).collect()
+}
+
+/// Collects the top-level `networks`, `volumes`, `secrets` and `configs` definitions the
+/// selected services refer to, so the merged output defines everything its services use.
+///
+/// When two sources define the same name differently, the first definition is kept.
+#[derive(Debug, Default, Clone)]
+pub struct DefinitionCollector {
+    collected: HashMap<TopLevelSection, Mapping>,
+    origins: HashMap<(TopLevelSection, String), String>,
+}
+
+impl DefinitionCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect the definitions `services` in `file` refer to, returning a warning for every
+    /// definition that clashed with an earlier one. `origin` describes the file in those warnings.
+    pub fn collect(
+        &mut self,
+        file: &DockerComposeFile,
+        services: &[String],
+        origin: &str,
+    ) -> Vec<String> {
+        let mut warnings = vec![];
+        for section in TopLevelSection::ALL {
+            let Some(definitions) = file.definitions(section) else {
+                continue;
+            };
+            let referenced = services
+                .iter()
+                .filter_map(|service| file.get_service(service))
+                .flat_map(|service| service_references(service, section));
+            for name in referenced {
+                let Some(definition) = definitions.get(name.as_str()) else {
+                    continue;
+                };
+                let collected = self.collected.entry(section).or_default();
+                match collected.get(name.as_str()) {
+                    None => {
+                        collected.insert(name.clone().into(), definition.clone());
+                        self.origins.insert((section, name), origin.to_string());
+                    }
+                    Some(existing) if existing == definition => {}
+                    Some(_) => {
+                        let first = &self.origins[&(section, name.clone())];
+                        let warning = format!(
+                            "{} `{name}` from {origin} differs from the one from {first}, keeping the first",
+                            section.key()
+                        );
+                        if !warnings.contains(&warning) {
+                            warnings.push(warning);
+                        }
+                    }
+                }
+            }
+        }
+        warnings
+    }
+
+    /// The collected definitions, by the top-level key of their section.
+    pub fn into_sections(self) -> Vec<(&'static str, Mapping)> {
+        TopLevelSection::ALL
+            .into_iter()
+            .filter_map(|section| {
+                let definitions = self.collected.get(&section)?;
+                Some((section.key(), definitions.clone()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collects_referenced_definitions() {
+        let file: DockerComposeFile = serde_yaml::from_str(
+            r#"
+            services:
+              db:
+                image: postgres
+                networks: [backend]
+                volumes:
+                  - db-data:/var/lib/postgresql/data
+                  - ./init.sql:/docker-entrypoint-initdb.d/init.sql
+                  - type: volume
+                    source: db-logs
+                    target: /logs
+                secrets: [db_password]
+              web:
+                image: web
+                networks:
+                  frontend:
+                    aliases: [www]
+                configs:
+                  - source: nginx
+                    target: /etc/nginx/nginx.conf
+            networks:
+              backend: {}
+              frontend: {}
+            volumes:
+              db-data: {}
+              db-logs:
+                driver: local
+              unused: {}
+            secrets:
+              db_password:
+                file: ./db_password.txt
+            configs:
+              nginx:
+                file: ./nginx.conf"#,
+        )
+        .unwrap();
+
+        let mut collector = DefinitionCollector::new();
+        let warnings = collector.collect(&file, &["db".to_string()], "first");
+        assert!(warnings.is_empty());
+        let sections: HashMap<&str, Mapping> = collector.into_sections().into_iter().collect();
+
+        let names =
+            |key: &str| -> Vec<&str> { sections[key].keys().filter_map(Value::as_str).collect() };
+        assert_eq!(names("networks"), vec!["backend"]);
+        assert_eq!(names("volumes"), vec!["db-data", "db-logs"]);
+        assert_eq!(sections["volumes"]["db-logs"]["driver"], "local");
+        assert_eq!(names("secrets"), vec!["db_password"]);
+        assert!(!sections.contains_key("configs"));
+    }
+
+    #[test]
+    fn test_clashing_definitions_keep_the_first() {
+        let parse = |driver: &str| -> DockerComposeFile {
+            serde_yaml::from_str(&format!(
+                "services:\n  api:\n    networks: [backend]\nnetworks:\n  backend:\n    driver: {driver}\n"
+            ))
+            .unwrap()
+        };
+        let services = ["api".to_string()];
+        let mut collector = DefinitionCollector::new();
+
+        assert!(
+            collector
+                .collect(&parse("bridge"), &services, "first")
+                .is_empty()
+        );
+        assert!(
+            collector
+                .collect(&parse("bridge"), &services, "same")
+                .is_empty()
+        );
+        let warnings = collector.collect(&parse("overlay"), &services, "second");
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("`backend` from second"));
+        let sections = collector.into_sections();
+        assert_eq!(sections[0].1["backend"]["driver"], "bridge");
+    }
+}
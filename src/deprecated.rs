@@ -0,0 +1,113 @@
+use std::fmt::Display;
+
+/// Top-level keys the Compose Specification has deprecated, with what to do instead.
+const DEPRECATED_TOP_LEVEL_KEYS: &[(&str, &str)] = &[(
+    "version",
+    "remove it, docker compose ignores it and picks the schema on its own",
+)];
+
+/// Service keys the Compose Specification has deprecated, with what to do instead.
+///
+/// There are none so far: `links` and `volumes_from` are legacy Docker features, but the
+/// specification still defines them without deprecating them, so they're valid.
+const DEPRECATED_SERVICE_KEYS: &[(&str, &str)] = &[];
+
+/// A deprecated key found in a compose file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deprecation {
+    /// The service the key was found in, or `None` for a top-level key.
+    pub service: Option<String>,
+    pub key: &'static str,
+    pub suggestion: &'static str,
+}
+
+impl Display for Deprecation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.service {
+            Some(service) => write!(
+                f,
+                "service `{service}` uses `{}`, which is deprecated: {}",
+                self.key, self.suggestion
+            ),
+            None => write!(
+                f,
+                "top-level `{}` is deprecated: {}",
+                self.key, self.suggestion
+            ),
+        }
+    }
+}
+
+/// Find the deprecated keys in the top level of a compose file and in each of its services.
+pub fn find_deprecations<'a>(
+    top_level: impl IntoIterator<Item = (&'a serde_yaml::Value, &'a serde_yaml::Value)>,
+) -> Vec<Deprecation> {
+    let mut deprecations = vec![];
+    let mut services = None;
+    for (key, value) in top_level {
+        match key.as_str() {
+            Some("services") => services = value.as_mapping(),
+            Some(key) => {
+                if let Some((key, suggestion)) = DEPRECATED_TOP_LEVEL_KEYS
+                    .iter()
+                    .find(|(deprecated, _)| *deprecated == key)
+                {
+                    deprecations.push(Deprecation {
+                        service: None,
+                        key,
+                        suggestion,
+                    });
+                }
+            }
+            None => {}
+        }
+    }
+    for (name, service) in services.into_iter().flatten() {
+        let (Some(name), Some(service)) = (name.as_str(), service.as_mapping()) else {
+            continue;
+        };
+        for (key, suggestion) in DEPRECATED_SERVICE_KEYS {
+            if service.contains_key(*key) {
+                deprecations.push(Deprecation {
+                    service: Some(name.to_string()),
+                    key,
+                    suggestion,
+                });
+            }
+        }
+    }
+    deprecations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_deprecations_allows_legacy_service_keys() {
+        let contents: serde_yaml::Mapping = serde_yaml::from_str(
+            r#"
+            services:
+              api:
+                image: api
+                links: [db]
+                volumes_from: [db]
+              db:
+                image: postgres"#,
+        )
+        .unwrap();
+
+        assert_eq!(find_deprecations(&contents), vec![]);
+    }
+
+    #[test]
+    fn test_find_deprecations_flags_top_level_version() {
+        let contents: serde_yaml::Mapping =
+            serde_yaml::from_str("version: '3'\nservices: {}").unwrap();
+
+        let deprecations = find_deprecations(&contents);
+        assert_eq!(deprecations.len(), 1);
+        assert_eq!(deprecations[0].service, None);
+        assert_eq!(deprecations[0].key, "version");
+    }
+}
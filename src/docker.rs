@@ -0,0 +1,123 @@
+use crate::YammerError;
+use std::collections::hash_map::RandomState;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The docker CLI, whose `compose config` validates compose files.
+pub const DOCKER: &str = "docker";
+
+/// Check the compose file at `path` with `<docker> compose -f <path> config --quiet`, resolving
+/// its relative paths (like `build` contexts and `env_file`s) against `project_dir`.
+///
+/// Returns whether the check ran: `false` if `docker` isn't installed. A file docker rejects
+/// is a [`YammerError::ComposeConfig`] with what docker said about it.
+pub fn docker_compose_config(
+    docker: &str,
+    path: &Path,
+    project_dir: &Path,
+) -> Result<bool, YammerError> {
+    let output = Command::new(docker)
+        .arg("compose")
+        .arg("-f")
+        .arg(path)
+        .arg("--project-directory")
+        .arg(project_dir)
+        .args(["config", "--quiet"])
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(source) => {
+            return Err(YammerError::Io {
+                path: docker.into(),
+                source,
+            });
+        }
+    };
+    if !output.status.success() {
+        return Err(YammerError::ComposeConfig(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(true)
+}
+
+/// Check `contents` like [`docker_compose_config`] does a file, by writing them to a temporary
+/// file that only the current user can read, and removing it again afterwards.
+pub fn docker_compose_check(
+    docker: &str,
+    contents: &str,
+    project_dir: &Path,
+) -> Result<bool, YammerError> {
+    let (path, mut file) = create_private_temp_file("dcompose-validate", "yml")?;
+    let checked = file
+        .write_all(contents.as_bytes())
+        .map_err(|source| YammerError::Io {
+            path: path.clone(),
+            source,
+        })
+        .and_then(|()| docker_compose_config(docker, &path, project_dir));
+    let _ = std::fs::remove_file(&path);
+    checked
+}
+
+/// Create a new file in the temporary directory under a random name, readable and writable
+/// only by the current user. Never opens a file that's already there.
+fn create_private_temp_file(prefix: &str, extension: &str) -> Result<(PathBuf, File), YammerError> {
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        let path =
+            std::env::temp_dir().join(format!("{prefix}-{:016x}.{extension}", hasher.finish()));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(source) => return Err(YammerError::Io { path, source }),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_docker_compose_config() {
+        let path = Path::new("docker-compose.yml");
+        assert!(docker_compose_config("true", path, Path::new(".")).unwrap());
+        assert!(!docker_compose_config("dcompose-no-such-docker", path, Path::new(".")).unwrap());
+        assert!(matches!(
+            docker_compose_config("false", path, Path::new(".")),
+            Err(YammerError::ComposeConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_docker_compose_check_uses_a_private_temp_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (first, _) = create_private_temp_file("dcompose-docker-test", "yml").unwrap();
+        let (second, _) = create_private_temp_file("dcompose-docker-test", "yml").unwrap();
+        assert_ne!(first, second);
+        let mode = std::fs::metadata(&first).unwrap().permissions().mode();
+        let _ = std::fs::remove_file(&first);
+        let _ = std::fs::remove_file(&second);
+        assert_eq!(mode & 0o777, 0o600);
+
+        assert!(docker_compose_check("true", "services: {}", Path::new(".")).unwrap());
+        assert!(
+            !docker_compose_check("dcompose-no-such-docker", "services: {}", Path::new("."))
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{DownloadFile, FileSource, YammerError, parse_env_file};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+
+/// The `env_file`s a service lists, as `(path, required)` pairs. Files are required unless
+/// the long syntax says `required: false`.
+fn env_files(service: &Mapping) -> Vec<(String, bool)> {
+    let entries = match service.get("env_file") {
+        Some(Value::Sequence(entries)) => entries.iter().collect(),
+        Some(entry) => vec![entry],
+        None => vec![],
+    };
+    entries
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Value::String(path) => Some((path.clone(), true)),
+            Value::Mapping(entry) => {
+                let path = entry.get("path")?.as_str()?.to_string();
+                let required = entry.get("required").and_then(Value::as_bool);
+                Some((path, required.unwrap_or(true)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Fetch the `env_file`s a service reads and inline their variables into its `environment`,
+/// so the service doesn't point at files that only exist next to its source.
+///
+/// The files are resolved relative to the compose file at `source`, so they're downloaded from
+/// the same repository and branch. Like compose, variables the service sets in `environment`
+/// win over the files, and later files win over earlier ones. Files that can't be downloaded
+/// are skipped if they're marked `required: false`. Returns whether anything was inlined, which
+/// is not the case for services without an `env_file`, or with an absolute one.
+pub async fn inline_env_files<D>(
+    downloader: &D,
+    source: &FileSource,
+    service: &mut Mapping,
+) -> Result<bool, YammerError>
+where
+    D: DownloadFile<FileSpec = FileSource> + Sync + ?Sized,
+{
+    let files = env_files(service);
+    if files.is_empty() || files.iter().any(|(path, _)| path.starts_with('/')) {
+        return Ok(false);
+    }
+    let mut variables = BTreeMap::new();
+    for (path, required) in files {
+        match downloader
+            .download_file(&source.resolve_relative(&path))
+            .await
+        {
+            Ok(contents) => variables.extend(parse_env_file(&String::from_utf8_lossy(&contents))),
+            Err(_) if !required => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    let environment = service
+        .entry("environment".into())
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    match environment {
+        Value::Mapping(environment) => {
+            for (key, value) in variables {
+                if !environment.contains_key(key.as_str()) {
+                    environment.insert(key.into(), value.into());
+                }
+            }
+        }
+        Value::Sequence(environment) => {
+            let set: Vec<String> = environment
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|entry| {
+                    entry
+                        .split_once('=')
+                        .map_or(entry, |(key, _)| key)
+                        .to_string()
+                })
+                .collect();
+            for (key, value) in variables {
+                if !set.contains(&key) {
+                    environment.push(format!("{key}={value}").into());
+                }
+            }
+        }
+        _ => return Ok(false),
+    }
+    service.remove("env_file");
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer, github_spec};
+    use crate::{GithubFileDownloader, SourceDownloader};
+
+    #[tokio::test]
+    async fn test_inline_env_files() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/org/repo/refs/heads/main/deploy/common.env" => {
+                MockResponse::ok("# Shared\nTZ=UTC\nLOG_LEVEL=info\n")
+            }
+            "/org/repo/refs/heads/main/deploy/api/api.env" => {
+                MockResponse::ok("LOG_LEVEL=debug\nPORT=8080\n")
+            }
+            _ => MockResponse::new(404, "not found"),
+        })
+        .await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let source = FileSource::Github(github_spec("repo", "deploy/docker-compose.yml"));
+
+        let mut service: Mapping = serde_yaml::from_str(
+            r#"
+            image: api
+            env_file:
+              - ./common.env
+              - path: ./api/api.env
+              - path: ./missing.env
+                required: false
+            environment:
+              PORT: "3000""#,
+        )
+        .unwrap();
+        assert!(
+            inline_env_files(&downloader, &source, &mut service)
+                .await
+                .unwrap()
+        );
+        assert!(!service.contains_key("env_file"));
+        let environment: Mapping =
+            serde_yaml::from_str("PORT: \"3000\"\nLOG_LEVEL: debug\nTZ: UTC\n").unwrap();
+        assert_eq!(service["environment"], Value::Mapping(environment));
+
+        let mut service: Mapping =
+            serde_yaml::from_str("env_file: common.env\nenvironment: [TZ=Europe/Paris]").unwrap();
+        inline_env_files(&downloader, &source, &mut service)
+            .await
+            .unwrap();
+        assert_eq!(
+            service["environment"],
+            serde_yaml::from_str::<Value>("[TZ=Europe/Paris, LOG_LEVEL=info]").unwrap()
+        );
+
+        let mut service: Mapping = serde_yaml::from_str("env_file: missing.env").unwrap();
+        assert!(
+            inline_env_files(&downloader, &source, &mut service)
+                .await
+                .is_err()
+        );
+        let mut service: Mapping = serde_yaml::from_str("image: redis").unwrap();
+        assert!(
+            !inline_env_files(&downloader, &source, &mut service)
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::service_dependencies;
+use serde_yaml::Value;
+
+/// Keep only the services that are enabled under at least one of the `active` profiles.
+///
+/// Like docker, services that don't declare any `profiles` are always enabled.
+pub fn filter_by_profiles(
+    services: &serde_yaml::Mapping,
+    active: &[String],
+) -> serde_yaml::Mapping {
+    services
+        .iter()
+        .filter(|(_, service)| match service.get("profiles") {
+            Some(Value::Sequence(profiles)) => profiles
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|profile| active.iter().any(|active| active == profile)),
+            _ => true,
+        })
+        .map(|(name, service)| (name.clone(), service.clone()))
+        .collect()
+}
+
+/// Remove the `dropped` services entirely, along with the `depends_on` and `links` entries
+/// of the remaining services that point at them. A `depends_on` or `links` left empty is
+/// removed too.
+pub fn drop_services(services: &serde_yaml::Mapping, dropped: &[String]) -> serde_yaml::Mapping {
+    let is_dropped_name = |name: &str| dropped.iter().any(|dropped| dropped == name);
+    let is_dropped = |name: &Value| name.as_str().is_some_and(is_dropped_name);
+    services
+        .iter()
+        .filter(|(name, _)| !is_dropped(name))
+        .map(|(name, service)| {
+            let mut service = service.clone();
+            let Some(service_mapping) = service.as_mapping_mut() else {
+                return (name.clone(), service);
+            };
+            match service_mapping.get_mut("depends_on") {
+                Some(Value::Sequence(dependencies)) => {
+                    dependencies.retain(|dependency| !is_dropped(dependency))
+                }
+                Some(Value::Mapping(dependencies)) => {
+                    dependencies.retain(|dependency, _| !is_dropped(dependency))
+                }
+                _ => {}
+            }
+            if let Some(Value::Sequence(links)) = service_mapping.get_mut("links") {
+                links.retain(|link| {
+                    !link
+                        .as_str()
+                        .is_some_and(|link| is_dropped_name(link.split(':').next().unwrap_or(link)))
+                });
+            }
+            for key in ["depends_on", "links"] {
+                let is_empty = match service_mapping.get(key) {
+                    Some(Value::Sequence(entries)) => entries.is_empty(),
+                    Some(Value::Mapping(entries)) => entries.is_empty(),
+                    _ => false,
+                };
+                if is_empty {
+                    service_mapping.remove(key);
+                }
+            }
+            (name.clone(), service)
+        })
+        .collect()
+}
+
+/// Keep only the `kept` services and the ones they depend on, directly or not, so that
+/// pruning never breaks a `depends_on`. Returns the remaining services, in their order, and
+/// the names of the pruned ones.
+pub fn prune_services(
+    services: &serde_yaml::Mapping,
+    kept: &[String],
+) -> (serde_yaml::Mapping, Vec<String>) {
+    let mut reachable: Vec<String> = kept
+        .iter()
+        .filter(|name| services.contains_key(name.as_str()))
+        .cloned()
+        .collect();
+    let mut next = 0;
+    while let Some(name) = reachable.get(next) {
+        let dependencies = services
+            .get(name.as_str())
+            .and_then(Value::as_mapping)
+            .map(service_dependencies)
+            .unwrap_or_default();
+        for dependency in dependencies {
+            if services.contains_key(dependency.as_str()) && !reachable.contains(&dependency) {
+                reachable.push(dependency);
+            }
+        }
+        next += 1;
+    }
+
+    let mut remaining = serde_yaml::Mapping::new();
+    let mut pruned = vec![];
+    for (name, service) in services {
+        match name.as_str() {
+            Some(name) if !reachable.iter().any(|kept| kept == name) => {
+                pruned.push(name.to_string())
+            }
+            _ => {
+                remaining.insert(name.clone(), service.clone());
+            }
+        }
+    }
+    (remaining, pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_by_profiles() {
+        let services: serde_yaml::Mapping = serde_yaml::from_str(
+            r#"
+            api:
+              image: api
+            debugger:
+              image: debugger
+              profiles: [debug]
+            mailhog:
+              image: mailhog
+              profiles: [dev, debug]
+            loadtest:
+              image: k6
+              profiles: [perf]"#,
+        )
+        .unwrap();
+
+        let filtered = filter_by_profiles(&services, &["debug".to_string()]);
+        let names: Vec<_> = filtered.keys().filter_map(Value::as_str).collect();
+        assert_eq!(names, vec!["api", "debugger", "mailhog"]);
+
+        let filtered = filter_by_profiles(&services, &[]);
+        let names: Vec<_> = filtered.keys().filter_map(Value::as_str).collect();
+        assert_eq!(names, vec!["api"]);
+    }
+
+    #[test]
+    fn test_prune_services_keeps_dependencies() {
+        let services: serde_yaml::Mapping = serde_yaml::from_str(
+            r#"
+            api:
+              image: api
+              depends_on:
+                cache:
+                  condition: service_started
+            cache:
+              image: redis
+              depends_on: [config]
+            config:
+              image: config
+            stale:
+              image: stale
+            pinned:
+              image: pinned"#,
+        )
+        .unwrap();
+
+        let (remaining, pruned) =
+            prune_services(&services, &["api".to_string(), "pinned".to_string()]);
+        let names: Vec<_> = remaining.keys().filter_map(Value::as_str).collect();
+        assert_eq!(names, vec!["api", "cache", "config", "pinned"]);
+        assert_eq!(pruned, vec!["stale"]);
+    }
+
+    #[test]
+    fn test_drop_services_cleans_up_dependencies() {
+        let services: serde_yaml::Mapping = serde_yaml::from_str(
+            r#"
+            api:
+              image: api
+              depends_on: [db, docs]
+              links: ["docs:documentation"]
+            worker:
+              image: worker
+              depends_on:
+                docs:
+                  condition: service_started
+            db:
+              image: postgres
+            docs:
+              image: docs"#,
+        )
+        .unwrap();
+
+        let dropped = drop_services(&services, &["docs".to_string()]);
+
+        let names: Vec<_> = dropped.keys().filter_map(Value::as_str).collect();
+        assert_eq!(names, vec!["api", "worker", "db"]);
+        assert_eq!(
+            dropped["api"]["depends_on"],
+            serde_yaml::from_str::<Value>("[db]").unwrap()
+        );
+        assert!(dropped["api"].get("links").is_none());
+        assert!(dropped["worker"].get("depends_on").is_none());
+    }
+}
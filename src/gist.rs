@@ -0,0 +1,259 @@
+use crate::{BearerToken, DownloadFile, YammerError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub const GITHUB_API_BASE: &str = "https://api.github.com";
+
+/// A single file in a GitHub Gist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GistFileSpec {
+    pub id: String,
+    pub filename: String,
+}
+
+impl GistFileSpec {
+    pub fn new(id: impl Into<String>, filename: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            filename: filename.into(),
+        }
+    }
+
+    pub fn get_url_on(&self, api_base: &str) -> String {
+        format!("{}/gists/{}", api_base.trim_end_matches('/'), self.id)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Gist {
+    files: HashMap<String, GistFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GistFile {
+    raw_url: String,
+    content: Option<String>,
+    #[serde(default)]
+    truncated: bool,
+}
+
+/// Downloads files out of GitHub Gists through the gist API.
+#[derive(Debug, Clone)]
+pub struct GistFileDownloader {
+    pub client: reqwest::Client,
+    pub api_base: String,
+    /// The most bytes a downloaded file may have.
+    pub max_file_size: u64,
+    /// A token to authenticate with, e.g. for secret gists.
+    pub token: Option<BearerToken>,
+}
+
+impl GistFileDownloader {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_base: GITHUB_API_BASE.to_string(),
+            max_file_size: crate::DEFAULT_MAX_FILE_SIZE,
+            token: None,
+        }
+    }
+
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    pub fn with_api_base(mut self, api_base: impl Into<String>) -> Self {
+        self.api_base = api_base.into();
+        self
+    }
+
+    /// Send `token` as an `Authorization: Bearer` header with every request.
+    pub fn with_token(mut self, token: BearerToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// The token to send to `url`, which is only ever the API itself: the raw URLs of large
+    /// files come out of API responses and may point anywhere.
+    fn token_for(&self, url: &str) -> Option<&BearerToken> {
+        let origin = |url: &str| {
+            reqwest::Url::parse(url).ok().map(|url| {
+                (
+                    url.host_str().map(str::to_owned),
+                    url.port_or_known_default(),
+                )
+            })
+        };
+        match origin(url) {
+            Some(url) if Some(&url) == origin(&self.api_base).as_ref() => self.token.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .get(url)
+            .header(reqwest::header::USER_AGENT, crate::USER_AGENT);
+        match self.token_for(url) {
+            Some(token) => token.apply(request),
+            None => request,
+        }
+    }
+}
+
+impl Default for GistFileDownloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl DownloadFile for GistFileDownloader {
+    type FileSpec = GistFileSpec;
+
+    fn source_url(&self, spec: &Self::FileSpec) -> Option<String> {
+        Some(spec.get_url_on(&self.api_base))
+    }
+
+    async fn download_file(&self, spec: &Self::FileSpec) -> Result<Bytes, YammerError> {
+        let url = spec.get_url_on(&self.api_base);
+        let response = self
+            .get(&url)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .send()
+            .await?;
+        let response = crate::github_refusal(&url, response, self.token_for(&url))
+            .await?
+            .error_for_status()?;
+        let gist: Gist =
+            serde_json::from_slice(&crate::read_body(&url, response, self.max_file_size).await?)?;
+
+        let Some(file) = gist.files.get(&spec.filename) else {
+            return Err(YammerError::MissingGistFile {
+                id: spec.id.clone(),
+                filename: spec.filename.clone(),
+            });
+        };
+        match &file.content {
+            // The API only inlines the first megabyte or so of each file.
+            Some(content) if !file.truncated => Ok(Bytes::from(content.clone())),
+            _ => {
+                let response = self.get(&file.raw_url).send().await?;
+                let response =
+                    crate::github_refusal(&file.raw_url, response, self.token_for(&file.raw_url))
+                        .await?
+                        .error_for_status()?;
+                crate::read_body(&file.raw_url, response, self.max_file_size).await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer};
+
+    #[tokio::test]
+    async fn test_download_gist_file() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/gists/abc123" => MockResponse::ok(
+                r#"{
+                    "id": "abc123",
+                    "files": {
+                        "README.md": {"raw_url": "unused", "content": "hi", "truncated": false},
+                        "docker-compose.yml": {
+                            "raw_url": "unused",
+                            "content": "services:\n  redis:\n    image: redis\n",
+                            "truncated": false
+                        }
+                    }
+                }"#,
+            )
+            .with_header("Content-Type", "application/json"),
+            _ => MockResponse::new(404, "{}"),
+        })
+        .await;
+        let downloader = GistFileDownloader::new().with_api_base(server.url());
+
+        let compose_file = downloader
+            .download_compose_file(&GistFileSpec::new("abc123", "docker-compose.yml"))
+            .await
+            .unwrap();
+        assert_eq!(compose_file.get_service("redis").unwrap()["image"], "redis");
+
+        let missing = downloader
+            .download_file(&GistFileSpec::new("abc123", "compose.yaml"))
+            .await;
+        assert!(matches!(missing, Err(YammerError::MissingGistFile { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_download_truncated_gist_file_uses_raw_url() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/gists/big" => MockResponse::ok(format!(
+                r#"{{"files": {{"docker-compose.yml": {{
+                    "raw_url": "http://{}/raw/big/docker-compose.yml",
+                    "content": "services:\n",
+                    "truncated": true
+                }}}}}}"#,
+                request.headers["host"]
+            )),
+            "/raw/big/docker-compose.yml" => {
+                MockResponse::ok("services:\n  redis:\n    image: redis\n")
+            }
+            _ => MockResponse::new(404, "{}"),
+        })
+        .await;
+        let downloader = GistFileDownloader::new().with_api_base(server.url());
+
+        let compose_file = downloader
+            .download_compose_file(&GistFileSpec::new("big", "docker-compose.yml"))
+            .await
+            .unwrap();
+        assert!(compose_file.get_service("redis").is_some());
+        assert_eq!(server.hits("/raw/big/docker-compose.yml"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_token_is_only_sent_to_the_api_host() {
+        let raw = MockServer::start(|request| match request.headers.get("authorization") {
+            Some(_) => MockResponse::new(500, "leaked"),
+            None => MockResponse::ok("services:\n  redis:\n    image: redis\n"),
+        })
+        .await;
+        let raw_url = format!("{}/raw/big/docker-compose.yml", raw.url());
+        let api = MockServer::start(move |request| {
+            match request.headers.get("authorization").map(String::as_str) {
+                Some("Bearer ghp_s3cret") => MockResponse::ok(format!(
+                    r#"{{"files": {{"docker-compose.yml": {{
+                        "raw_url": "{raw_url}",
+                        "content": "services:\n",
+                        "truncated": true
+                    }}}}}}"#
+                )),
+                _ => MockResponse::new(404, "{}"),
+            }
+        })
+        .await;
+        let downloader = GistFileDownloader::new()
+            .with_api_base(api.url())
+            .with_token(BearerToken::new("ghp_s3cret"));
+
+        let compose_file = downloader
+            .download_compose_file(&GistFileSpec::new("big", "docker-compose.yml"))
+            .await
+            .unwrap();
+        assert!(compose_file.get_service("redis").is_some());
+        assert_eq!(raw.hits("/raw/big/docker-compose.yml"), 1);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{CacheKey, DEFAULT_BRANCH, DownloadFile, YammerError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use regex::Regex;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+pub const GITLAB_HOST: &str = "https://gitlab.com";
+
+/// Matches the file part of a GitLab DSN, `group/project+branch:path`. Projects can be nested
+/// in any number of groups.
+pub static GITLAB_SPEC_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?<project>[^+:@]+/[^+:@]+)(?:\+(?<branch>[^:@]+))?(?::(?<path>[^@]+))?$")
+        .expect("should be able to compile basic gitlab project regex")
+});
+
+/// A file in a GitLab repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitlabFileSpec<S> {
+    /// The path of the project, including its groups, e.g. `group/subgroup/project`.
+    pub project: S,
+    pub branch: S,
+    pub filepath: S,
+}
+
+impl<S> GitlabFileSpec<S> {
+    pub fn new(project: S, branch: S, filepath: S) -> Self {
+        Self {
+            project,
+            branch,
+            filepath,
+        }
+    }
+}
+
+impl<S: AsRef<str>> GitlabFileSpec<S> {
+    pub fn get_url(&self) -> String {
+        self.get_url_on(GITLAB_HOST)
+    }
+
+    /// Build the raw file url against a self-hosted instance.
+    pub fn get_url_on(&self, base: &str) -> String {
+        format!(
+            "{}/{}/-/raw/{}/{}",
+            base.trim_end_matches('/'),
+            crate::encode_path(self.project.as_ref()),
+            crate::encode_path(self.branch.as_ref()),
+            crate::encode_path(self.filepath.as_ref()),
+        )
+    }
+}
+
+impl<S: AsRef<str>> CacheKey for GitlabFileSpec<S> {
+    fn cache_key(&self) -> String {
+        self.get_url()
+    }
+
+    fn is_immutable(&self) -> bool {
+        crate::is_commit_sha(self.branch.as_ref())
+    }
+}
+
+/// Parses `group/project+branch:path`, where the branch defaults to [`DEFAULT_BRANCH`].
+impl FromStr for GitlabFileSpec<String> {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(captures) = GITLAB_SPEC_RE.captures(s) else {
+            return Err(YammerError::UnknownSpec(
+                "expected gitlab:<group>/<project>+<branch>:<path>@<services>".to_string(),
+            ));
+        };
+        Ok(GitlabFileSpec::new(
+            captures["project"].to_string(),
+            captures
+                .name("branch")
+                .map_or(DEFAULT_BRANCH, |m| m.as_str())
+                .to_string(),
+            captures.name("path").map_or("", |m| m.as_str()).to_string(),
+        ))
+    }
+}
+
+/// Downloads raw files from gitlab.com or a self-hosted GitLab instance.
+///
+/// Requests aren't authenticated, so only files in public projects can be downloaded.
+#[derive(Debug, Clone)]
+pub struct GitlabFileDownloader {
+    pub client: reqwest::Client,
+    pub base: String,
+    /// The most bytes a downloaded file may have.
+    pub max_file_size: u64,
+}
+
+impl GitlabFileDownloader {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base: GITLAB_HOST.to_string(),
+            max_file_size: crate::DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Download from a self-hosted instance instead of gitlab.com.
+    pub fn with_base(mut self, base: impl Into<String>) -> Self {
+        self.base = base.into();
+        self
+    }
+}
+
+impl Default for GitlabFileDownloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl DownloadFile for GitlabFileDownloader {
+    type FileSpec = GitlabFileSpec<String>;
+
+    fn source_url(&self, spec: &Self::FileSpec) -> Option<String> {
+        Some(spec.get_url_on(&self.base))
+    }
+
+    async fn download_file(&self, spec: &Self::FileSpec) -> Result<Bytes, YammerError> {
+        let url = spec.get_url_on(&self.base);
+        let response = self
+            .client
+            .get(&url)
+            .header(reqwest::header::USER_AGENT, crate::USER_AGENT)
+            .send()
+            .await?;
+        let response = crate::refusal(&url, response).await?.error_for_status()?;
+        crate::read_body(&url, response, self.max_file_size).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer};
+
+    #[test]
+    fn test_gitlab_file_spec_from_str() {
+        let spec: GitlabFileSpec<String> =
+            "group/sub/project+main:deploy/compose.yml".parse().unwrap();
+        assert_eq!(spec.project, "group/sub/project");
+        assert_eq!(spec.branch, "main");
+        assert_eq!(spec.filepath, "deploy/compose.yml");
+        assert_eq!(
+            spec.get_url(),
+            "https://gitlab.com/group/sub/project/-/raw/main/deploy/compose.yml"
+        );
+
+        let spec: GitlabFileSpec<String> = "group/project:my compose#1.yml".parse().unwrap();
+        assert_eq!(
+            spec.get_url(),
+            format!("https://gitlab.com/group/project/-/raw/{DEFAULT_BRANCH}/my%20compose%231.yml")
+        );
+        assert_eq!(spec.branch, DEFAULT_BRANCH);
+        assert!(
+            "project:compose.yml"
+                .parse::<GitlabFileSpec<String>>()
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_gitlab_compose_file() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/group/project/-/raw/main/docker-compose.yml" => {
+                MockResponse::ok("services:\n  redis:\n    image: redis\n")
+            }
+            _ => MockResponse::new(404, "Not Found"),
+        })
+        .await;
+        let downloader = GitlabFileDownloader::new().with_base(server.url());
+
+        let compose_file = downloader
+            .download_compose_file(&GitlabFileSpec::new(
+                "group/project".to_string(),
+                "main".to_string(),
+                "docker-compose.yml".to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(compose_file.get_service("redis").unwrap()["image"], "redis");
+    }
+}
@@ -0,0 +1,328 @@
+use crate::{DockerComposeFile, YammerError};
+use serde_yaml::Value;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// The names of the services a service `depends_on`.
+///
+/// Both the short list form (`depends_on: [db]`) and the long mapping form
+/// (`depends_on: {db: {condition: service_healthy}}`) are understood.
+pub fn service_dependencies(service: &serde_yaml::Mapping) -> Vec<String> {
+    match service.get("depends_on") {
+        Some(Value::Sequence(dependencies)) => dependencies
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        Some(Value::Mapping(dependencies)) => dependencies
+            .keys()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// The names of the services a service `links` to, without any link aliases.
+pub fn service_links(service: &serde_yaml::Mapping) -> Vec<String> {
+    match service.get("links") {
+        Some(Value::Sequence(links)) => links
+            .iter()
+            .filter_map(Value::as_str)
+            .map(|link| {
+                link.split_once(':')
+                    .map_or(link, |(name, _)| name)
+                    .to_string()
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Find the `depends_on` cycles among the services, each as the path of services around it
+/// (starting from the service the cycle was entered at, without repeating it at the end).
+///
+/// Dependencies on services that aren't in `services` are ignored.
+pub fn dependency_cycles(services: &serde_yaml::Mapping) -> Vec<Vec<String>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Visiting,
+        Done,
+    }
+
+    fn visit(
+        name: &str,
+        services: &serde_yaml::Mapping,
+        states: &mut HashMap<String, State>,
+        path: &mut Vec<String>,
+        cycles: &mut Vec<Vec<String>>,
+    ) {
+        states.insert(name.to_string(), State::Visiting);
+        path.push(name.to_string());
+        let dependencies = services
+            .get(name)
+            .and_then(Value::as_mapping)
+            .map(service_dependencies)
+            .unwrap_or_default();
+        for dependency in dependencies {
+            if !services.contains_key(dependency.as_str()) {
+                continue;
+            }
+            match states.get(&dependency) {
+                Some(State::Visiting) => {
+                    let start = path
+                        .iter()
+                        .position(|service| *service == dependency)
+                        .expect("a service being visited is on the path");
+                    cycles.push(path[start..].to_vec());
+                }
+                Some(State::Done) => {}
+                None => visit(&dependency, services, states, path, cycles),
+            }
+        }
+        path.pop();
+        states.insert(name.to_string(), State::Done);
+    }
+
+    let mut states = HashMap::new();
+    let mut cycles = vec![];
+    for name in services.keys().filter_map(Value::as_str) {
+        if !states.contains_key(name) {
+            visit(name, services, &mut states, &mut vec![], &mut cycles);
+        }
+    }
+    cycles
+}
+
+impl DockerComposeFile {
+    /// The services in `names` along with every service they `depends_on`, directly or through
+    /// other services: the names first, then their dependencies in the order they're reached.
+    ///
+    /// Dependencies this file doesn't define are left out, and cycles are only walked once.
+    pub fn resolve_dependencies(&self, names: &[String]) -> Vec<String> {
+        let mut resolved: Vec<String> = vec![];
+        for name in names {
+            if !resolved.contains(name) {
+                resolved.push(name.clone());
+            }
+        }
+        let mut next = 0;
+        while let Some(name) = resolved.get(next) {
+            let dependencies = self
+                .get_service(name)
+                .map(service_dependencies)
+                .unwrap_or_default();
+            for dependency in dependencies {
+                if self.get_service(&dependency).is_some() && !resolved.contains(&dependency) {
+                    resolved.push(dependency);
+                }
+            }
+            next += 1;
+        }
+        resolved
+    }
+
+    /// The services that depend on `name`, directly or through other services, in the order
+    /// this file defines them. `name` itself is never included, even in a cycle.
+    pub fn reverse_dependencies(&self, name: &str) -> Vec<String> {
+        let Some(services) = &self.services else {
+            return vec![];
+        };
+        let mut dependents: HashSet<String> = HashSet::new();
+        let mut pending = vec![name.to_string()];
+        while let Some(dependency) = pending.pop() {
+            for (service, contents) in services {
+                let (Some(service), Some(contents)) = (service.as_str(), contents.as_mapping())
+                else {
+                    continue;
+                };
+                if service != name
+                    && !dependents.contains(service)
+                    && service_dependencies(contents).contains(&dependency)
+                {
+                    dependents.insert(service.to_string());
+                    pending.push(service.to_string());
+                }
+            }
+        }
+        services
+            .keys()
+            .filter_map(Value::as_str)
+            .filter(|service| dependents.contains(*service))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// A format the dependency graph of the merged services can be emitted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// A Graphviz DOT digraph.
+    Dot,
+}
+
+impl FromStr for GraphFormat {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            _ => Err(YammerError::UnknownGraphFormat(s.to_string())),
+        }
+    }
+}
+
+impl GraphFormat {
+    pub fn render(&self, services: &serde_yaml::Mapping) -> String {
+        match self {
+            GraphFormat::Dot => dependency_graph_dot(services),
+        }
+    }
+}
+
+/// Render the services as a Graphviz DOT digraph, with an edge from each service to every
+/// service it `depends_on` (solid) or `links` to (dashed).
+pub fn dependency_graph_dot(services: &serde_yaml::Mapping) -> String {
+    fn quote(name: &str) -> String {
+        format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+
+    let mut dot = String::from("digraph services {\n");
+    for name in services.keys().filter_map(Value::as_str) {
+        dot.push_str(&format!("    {};\n", quote(name)));
+    }
+    for (name, service) in services {
+        let (Some(name), Some(service)) = (name.as_str(), service.as_mapping()) else {
+            continue;
+        };
+        for dependency in service_dependencies(service) {
+            dot.push_str(&format!("    {} -> {};\n", quote(name), quote(&dependency)));
+        }
+        for link in service_links(service) {
+            dot.push_str(&format!(
+                "    {} -> {} [style=dashed];\n",
+                quote(name),
+                quote(&link)
+            ));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_dependencies_short_and_long_form() {
+        let short: serde_yaml::Mapping = serde_yaml::from_str("depends_on: [db, cache]").unwrap();
+        assert_eq!(service_dependencies(&short), vec!["db", "cache"]);
+
+        let long: serde_yaml::Mapping = serde_yaml::from_str(
+            r#"
+            depends_on:
+              db:
+                condition: service_healthy"#,
+        )
+        .unwrap();
+        assert_eq!(service_dependencies(&long), vec!["db"]);
+
+        let none: serde_yaml::Mapping = serde_yaml::from_str("image: redis").unwrap();
+        assert!(service_dependencies(&none).is_empty());
+    }
+
+    #[test]
+    fn test_dependency_graph_dot() {
+        let services: serde_yaml::Mapping = serde_yaml::from_str(
+            r#"
+            api:
+              image: api
+              depends_on: [db]
+              links: ["cache:redis"]
+            db:
+              image: postgres"#,
+        )
+        .unwrap();
+
+        let dot = GraphFormat::Dot.render(&services);
+
+        assert!(dot.starts_with("digraph services {\n"));
+        assert!(dot.contains("    \"api\";\n"));
+        assert!(dot.contains("    \"db\";\n"));
+        assert!(dot.contains("    \"api\" -> \"db\";\n"));
+        assert!(dot.contains("    \"api\" -> \"cache\" [style=dashed];\n"));
+        assert!(!dot.contains("\"db\" ->"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_reverse_dependencies_pulls_in_every_dependent() {
+        let compose_file: DockerComposeFile = serde_yaml::from_str(
+            r#"
+            services:
+              api:
+                image: api
+                depends_on: [db]
+              db:
+                image: postgres
+              docs:
+                image: docs
+              worker:
+                image: worker
+                depends_on:
+                  db:
+                    condition: service_healthy
+              scheduler:
+                image: scheduler
+                depends_on: [worker]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            compose_file.reverse_dependencies("db"),
+            vec!["api", "worker", "scheduler"]
+        );
+        assert!(compose_file.reverse_dependencies("docs").is_empty());
+    }
+
+    #[test]
+    fn test_resolve_dependencies_follows_the_whole_graph() {
+        let compose_file: DockerComposeFile = serde_yaml::from_str(
+            r#"
+            services:
+              api:
+                image: api
+                depends_on: [worker, db]
+              worker:
+                image: worker
+                depends_on:
+                  queue:
+                    condition: service_started
+                  external: {}
+              queue:
+                image: rabbitmq
+                depends_on: [worker]
+              db:
+                image: postgres
+              docs:
+                image: docs"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            compose_file.resolve_dependencies(&["api".to_string()]),
+            vec!["api", "worker", "db", "queue"]
+        );
+        assert_eq!(
+            compose_file.resolve_dependencies(&["queue".to_string(), "docs".to_string()]),
+            vec!["queue", "docs", "worker"]
+        );
+    }
+
+    #[test]
+    fn test_graph_format_from_str() {
+        assert_eq!("dot".parse::<GraphFormat>().unwrap(), GraphFormat::Dot);
+        assert!("svg".parse::<GraphFormat>().is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use crate::YammerError;
+use serde_yaml::{Mapping, Value};
+use sha2::{Digest, Sha256};
+
+/// The canonical form of a value: mapping keys sorted at every level, and `environment` and
+/// `labels` given as `KEY=VALUE` lists turned into the equivalent mappings, so that contents
+/// that mean the same to compose have the same canonical form.
+pub fn canonicalize(value: &Value) -> Value {
+    match value {
+        Value::Mapping(mapping) => {
+            let mut entries: Vec<(Value, Value)> = mapping
+                .iter()
+                .map(|(key, value)| {
+                    let value = match (key.as_str(), value) {
+                        (Some("environment" | "labels"), Value::Sequence(entries)) => {
+                            key_value_mapping(entries).unwrap_or_else(|| value.clone())
+                        }
+                        _ => value.clone(),
+                    };
+                    (key.clone(), canonicalize(&value))
+                })
+                .collect();
+            entries.sort_by_cached_key(|(key, _)| serde_yaml::to_string(key).unwrap_or_default());
+            Value::Mapping(entries.into_iter().collect())
+        }
+        Value::Sequence(sequence) => Value::Sequence(sequence.iter().map(canonicalize).collect()),
+        Value::Tagged(tagged) => canonicalize(&tagged.value),
+        value => value.clone(),
+    }
+}
+
+/// `KEY=VALUE` entries as a mapping, with entries that have no `=` mapped to null.
+/// `None` if any entry isn't a string.
+fn key_value_mapping(entries: &[Value]) -> Option<Value> {
+    let mut mapping = Mapping::new();
+    for entry in entries {
+        let entry = entry.as_str()?;
+        match entry.split_once('=') {
+            Some((key, value)) => mapping.insert(key.into(), value.into()),
+            None => mapping.insert(entry.into(), Value::Null),
+        };
+    }
+    Some(Value::Mapping(mapping))
+}
+
+/// The hex SHA256 of the canonical YAML of `contents`, see [`canonicalize`].
+pub fn canonical_hash(contents: &impl serde::Serialize) -> Result<String, YammerError> {
+    let canonical = canonicalize(&serde_yaml::to_value(contents)?);
+    let digest = Sha256::digest(serde_yaml::to_string(&canonical)?.as_bytes());
+    Ok(digest.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_canonical_hash_is_stable_and_order_insensitive() {
+        let parse = |yaml: &str| -> HashMap<Value, Value> { serde_yaml::from_str(yaml).unwrap() };
+        let first = parse(
+            r#"
+            version: '3'
+            services:
+              redis:
+                image: redis
+                environment: [TZ=UTC, DEBUG]
+              api:
+                image: api
+                ports: ["8080:80"]"#,
+        );
+        let reordered = parse(
+            r#"
+            services:
+              api:
+                ports: ["8080:80"]
+                image: api
+              redis:
+                environment:
+                  DEBUG:
+                  TZ: UTC
+                image: redis
+            version: '3'"#,
+        );
+
+        let hash = canonical_hash(&first).unwrap();
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, canonical_hash(&first).unwrap());
+        assert_eq!(hash, canonical_hash(&reordered).unwrap());
+
+        let changed = parse("version: '3'\nservices:\n  redis:\n    image: redis:7\n");
+        assert_ne!(hash, canonical_hash(&changed).unwrap());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{CacheKey, DockerComposeFile, DownloadFile, FileSource, YammerError};
+use std::collections::HashSet;
+
+/// The paths a compose file pulls in through its top-level `include`, in order.
+///
+/// Entries can be a plain path, or a mapping whose `path` is a path or a list of them.
+pub fn include_paths(file: &DockerComposeFile) -> Vec<String> {
+    let Some(serde_yaml::Value::Sequence(includes)) = file.other.get("include") else {
+        return vec![];
+    };
+    let mut paths = vec![];
+    for include in includes {
+        match include {
+            serde_yaml::Value::String(path) => paths.push(path.clone()),
+            serde_yaml::Value::Mapping(include) => match include.get("path") {
+                Some(serde_yaml::Value::String(path)) => paths.push(path.clone()),
+                Some(serde_yaml::Value::Sequence(path)) => paths.extend(
+                    path.iter()
+                        .filter_map(serde_yaml::Value::as_str)
+                        .map(str::to_string),
+                ),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    paths
+}
+
+/// Add everything from `included` that `file` doesn't define itself. Top-level mappings like
+/// `services`, `networks` and `volumes` are combined entry by entry.
+fn absorb(file: &mut DockerComposeFile, included: DockerComposeFile) {
+    if let Some(included_services) = included.services {
+        let services = file.services.get_or_insert_with(Default::default);
+        for (name, service) in included_services {
+            if !services.contains_key(&name) {
+                services.insert(name, service);
+            }
+        }
+    }
+    for (key, value) in included.other {
+        if key.as_str() == Some("include") {
+            continue;
+        }
+        match (file.other.get_mut(&key), value) {
+            (Some(serde_yaml::Value::Mapping(existing)), serde_yaml::Value::Mapping(value)) => {
+                for (name, entry) in value {
+                    if !existing.contains_key(&name) {
+                        existing.insert(name, entry);
+                    }
+                }
+            }
+            (Some(_), _) => {}
+            (None, value) => {
+                file.other.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Download every file `file` includes, recursively, and inline their contents into it,
+/// leaving no `include` key behind.
+///
+/// Include paths are resolved relative to the file that includes them. Definitions in the
+/// including file win over included ones, and a file that's included more than once is only
+/// inlined the first time.
+pub async fn flatten_includes<D>(
+    downloader: &D,
+    source: &FileSource,
+    mut file: DockerComposeFile,
+) -> Result<DockerComposeFile, YammerError>
+where
+    D: DownloadFile<FileSpec = FileSource> + Sync + ?Sized,
+{
+    let mut seen = HashSet::from([source.cache_key()]);
+    let mut pending: Vec<FileSource> = include_paths(&file)
+        .iter()
+        .rev()
+        .map(|path| source.resolve_relative(path))
+        .collect();
+    file.other.remove("include");
+
+    while let Some(source) = pending.pop() {
+        if !seen.insert(source.cache_key()) {
+            continue;
+        }
+        let included = downloader.download_compose_file(&source).await?;
+        pending.extend(
+            include_paths(&included)
+                .iter()
+                .rev()
+                .map(|path| source.resolve_relative(path)),
+        );
+        absorb(&mut file, included);
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer, github_spec};
+    use crate::{GithubFileDownloader, SourceDownloader};
+
+    #[tokio::test]
+    async fn test_flatten_includes_inlines_included_services() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/org/repo/refs/heads/main/deploy/docker-compose.yml" => MockResponse::ok(
+                r#"
+include:
+  - ./db/compose.yml
+  - path: [../cache.yml]
+services:
+  api:
+    image: api
+"#,
+            ),
+            "/org/repo/refs/heads/main/deploy/db/compose.yml" => MockResponse::ok(
+                r#"
+include: [../../cache.yml]
+services:
+  db:
+    image: postgres
+volumes:
+  db-data: {}
+"#,
+            ),
+            "/org/repo/refs/heads/main/cache.yml" => {
+                MockResponse::ok("services:\n  cache:\n    image: redis\n")
+            }
+            _ => MockResponse::new(404, "not found"),
+        })
+        .await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let source = FileSource::Github(github_spec("repo", "deploy/docker-compose.yml"));
+        let file = downloader.download_compose_file(&source).await.unwrap();
+        assert_eq!(
+            include_paths(&file),
+            vec!["./db/compose.yml", "../cache.yml"]
+        );
+
+        let flattened = flatten_includes(&downloader, &source, file).await.unwrap();
+
+        assert!(!flattened.other.contains_key("include"));
+        assert!(flattened.get_service("api").is_some());
+        assert_eq!(flattened.get_service("db").unwrap()["image"], "postgres");
+        assert_eq!(flattened.get_service("cache").unwrap()["image"], "redis");
+        assert!(
+            flattened.other["volumes"]
+                .as_mapping()
+                .unwrap()
+                .contains_key("db-data")
+        );
+        assert_eq!(server.hits("/org/repo/refs/heads/main/cache.yml"), 1);
+    }
+}
@@ -0,0 +1,310 @@
+use crate::{KeyValue, YammerError};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Looks up the values of `${VAR}` references from several layers of variables.
+///
+/// From highest to lowest precedence: values given with `--set`, then values from
+/// `--env-file`s (later files win over earlier ones), then the process environment.
+#[derive(Debug, Clone, Default)]
+pub struct EnvResolver {
+    overrides: HashMap<String, String>,
+    env_file: HashMap<String, String>,
+    process: HashMap<String, String>,
+    require_set: bool,
+}
+
+impl EnvResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fall back to the variables of this process.
+    pub fn with_process_env(mut self) -> Self {
+        self.process = std::env::vars().collect();
+        self
+    }
+
+    /// Add the variables of an env file, overriding those of env files added before it.
+    pub fn with_env_file(mut self, vars: HashMap<String, String>) -> Self {
+        self.env_file.extend(vars);
+        self
+    }
+
+    /// Add values that take precedence over everything else, like those given with `--set`.
+    pub fn with_overrides<'a>(mut self, values: impl IntoIterator<Item = &'a KeyValue>) -> Self {
+        self.overrides.extend(
+            values
+                .into_iter()
+                .map(|value| (value.key.clone(), value.value.clone())),
+        );
+        self
+    }
+
+    /// Fail on references to unset variables that have no default, instead of leaving them
+    /// as they are.
+    pub fn with_require_set(mut self, require_set: bool) -> Self {
+        self.require_set = require_set;
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.overrides
+            .get(name)
+            .or_else(|| self.env_file.get(name))
+            .or_else(|| self.process.get(name))
+            .map(String::as_str)
+    }
+}
+
+/// Parse the `KEY=VALUE` lines of an env file, like docker's `--env-file`.
+///
+/// Blank lines and `#` comments are skipped, a leading `export ` is ignored and values
+/// wrapped in matching single or double quotes are unquoted.
+pub fn parse_env_file(contents: &str) -> HashMap<String, String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let value = value.trim();
+            let value = [('"', '"'), ('\'', '\'')]
+                .iter()
+                .find_map(|(open, close)| {
+                    value
+                        .strip_prefix(*open)
+                        .and_then(|value| value.strip_suffix(*close))
+                })
+                .unwrap_or(value);
+            Some((key.trim().to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Read an env file from disk. See [`parse_env_file`].
+pub fn read_env_file(path: impl AsRef<Path>) -> Result<HashMap<String, String>, YammerError> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path).map_err(|source| YammerError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(parse_env_file(&contents))
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Substitute `$VAR`, `${VAR}`, `${VAR:-default}`, `${VAR-default}`, `${VAR:?error}` and
+/// `${VAR?error}` references in `text`.
+///
+/// `:-` falls back to the default when the variable is unset or empty, `-` only when it's
+/// unset. `:?` fails with the error when the variable is unset or empty, `?` only when it's
+/// unset. References to unset variables without a default are left as they are, unless the
+/// resolver requires them to be set, and the `$$` escape is always left alone.
+pub fn interpolate(text: &str, resolver: &EnvResolver) -> Result<String, YammerError> {
+    let unset = |name: &str, message: &str| YammerError::UnsetVariable {
+        name: name.to_string(),
+        message: match message {
+            "" => "it has no default".to_string(),
+            message => message.to_string(),
+        },
+    };
+    let mut interpolated = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(dollar) = rest.find('$') {
+        interpolated.push_str(&rest[..dollar]);
+        let after = &rest[dollar + 1..];
+
+        if let Some(escaped) = after.strip_prefix('$') {
+            interpolated.push_str("$$");
+            rest = escaped;
+        } else if let Some(braced) = after.strip_prefix('{')
+            && let Some(end) = braced.find('}')
+        {
+            let reference = &braced[..end];
+            let name_end = reference
+                .find(|c: char| !is_name_char(c))
+                .unwrap_or(reference.len());
+            let (name, modifier) = reference.split_at(name_end);
+            let value = resolver.get(name);
+            let set = value.filter(|value| !value.is_empty());
+            let substituted = if name.is_empty() {
+                None
+            } else if let Some(default) = modifier.strip_prefix(":-") {
+                Some(set.unwrap_or(default))
+            } else if let Some(default) = modifier.strip_prefix('-') {
+                Some(value.unwrap_or(default))
+            } else if let Some(message) = modifier.strip_prefix(":?") {
+                Some(set.ok_or_else(|| unset(name, message))?)
+            } else if let Some(message) = modifier.strip_prefix('?') {
+                Some(value.ok_or_else(|| unset(name, message))?)
+            } else if modifier.is_empty() && resolver.require_set {
+                Some(value.ok_or_else(|| unset(name, ""))?)
+            } else if modifier.is_empty() {
+                value
+            } else {
+                None
+            };
+            match substituted {
+                Some(value) => interpolated.push_str(value),
+                None => interpolated.push_str(&rest[dollar..dollar + 2 + end + 1]),
+            }
+            rest = &braced[end + 1..];
+        } else {
+            let name_end = after
+                .find(|c: char| !is_name_char(c))
+                .unwrap_or(after.len());
+            let name = &after[..name_end];
+            match resolver.get(name).filter(|_| !name.is_empty()) {
+                Some(value) => interpolated.push_str(value),
+                None if resolver.require_set && !name.is_empty() => return Err(unset(name, "")),
+                None => {
+                    interpolated.push('$');
+                    interpolated.push_str(name);
+                }
+            }
+            rest = &after[name_end..];
+        }
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+/// Interpolate every string in a YAML value, including mapping keys. See [`interpolate`].
+pub fn interpolate_value(
+    value: &mut serde_yaml::Value,
+    resolver: &EnvResolver,
+) -> Result<(), YammerError> {
+    match value {
+        serde_yaml::Value::String(text) => *text = interpolate(text, resolver)?,
+        serde_yaml::Value::Sequence(values) => {
+            for value in values {
+                interpolate_value(value, resolver)?;
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => interpolate_mapping(mapping, resolver)?,
+        serde_yaml::Value::Tagged(tagged) => interpolate_value(&mut tagged.value, resolver)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Interpolate every key and value of a mapping, such as a single service.
+pub fn interpolate_mapping(
+    mapping: &mut serde_yaml::Mapping,
+    resolver: &EnvResolver,
+) -> Result<(), YammerError> {
+    *mapping = std::mem::take(mapping)
+        .into_iter()
+        .map(|(mut key, mut value)| {
+            interpolate_value(&mut key, resolver)?;
+            interpolate_value(&mut value, resolver)?;
+            Ok((key, value))
+        })
+        .collect::<Result<_, YammerError>>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver() -> EnvResolver {
+        let mut process = HashMap::new();
+        process.insert("TAG".to_string(), "from-process".to_string());
+        process.insert("HOST".to_string(), "from-process".to_string());
+        process.insert("PORT".to_string(), "from-process".to_string());
+        process.insert("EMPTY".to_string(), "".to_string());
+        EnvResolver {
+            process,
+            ..Default::default()
+        }
+        .with_env_file(parse_env_file(
+            "TAG=from-env-file\nexport HOST='from-env-file'\n",
+        ))
+        .with_overrides(&["TAG=from-set".parse::<KeyValue>().unwrap()])
+    }
+
+    #[test]
+    fn test_env_resolver_precedence() {
+        let resolver = resolver();
+        assert_eq!(resolver.get("TAG"), Some("from-set"));
+        assert_eq!(resolver.get("HOST"), Some("from-env-file"));
+        assert_eq!(resolver.get("PORT"), Some("from-process"));
+        assert_eq!(resolver.get("MISSING"), None);
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let resolver = resolver();
+        assert_eq!(
+            interpolate("image: app:${TAG} on $HOST:${PORT}", &resolver).unwrap(),
+            "image: app:from-set on from-env-file:from-process"
+        );
+        assert_eq!(
+            interpolate("${MISSING:-fallback}", &resolver).unwrap(),
+            "fallback"
+        );
+        assert_eq!(
+            interpolate("${EMPTY:-fallback}", &resolver).unwrap(),
+            "fallback"
+        );
+        assert_eq!(interpolate("${EMPTY-fallback}", &resolver).unwrap(), "");
+        assert_eq!(
+            interpolate("${MISSING} $MISSING", &resolver).unwrap(),
+            "${MISSING} $MISSING"
+        );
+        assert_eq!(
+            interpolate("$${TAG} costs $$5", &resolver).unwrap(),
+            "$${TAG} costs $$5"
+        );
+        assert_eq!(interpolate("${TAG", &resolver).unwrap(), "${TAG");
+    }
+
+    #[test]
+    fn test_interpolate_required_variables() {
+        let resolver = resolver();
+        assert_eq!(
+            interpolate("${TAG:?tag is required}", &resolver).unwrap(),
+            "from-set"
+        );
+        assert_eq!(interpolate("${EMPTY?unused}", &resolver).unwrap(), "");
+        let err = interpolate("${EMPTY:?set a tag}", &resolver).unwrap_err();
+        assert!(
+            matches!(&err, YammerError::UnsetVariable { name, message } if name == "EMPTY" && message == "set a tag")
+        );
+        assert!(interpolate("${MISSING?}", &resolver).is_err());
+
+        let strict = resolver.with_require_set(true);
+        assert!(matches!(
+            interpolate("app:${MISSING}", &strict),
+            Err(YammerError::UnsetVariable { .. })
+        ));
+        assert!(interpolate("$MISSING", &strict).is_err());
+        assert_eq!(
+            interpolate("${MISSING:-fallback} $$MISSING", &strict).unwrap(),
+            "fallback $$MISSING"
+        );
+    }
+
+    #[test]
+    fn test_interpolate_value() {
+        let mut value: serde_yaml::Value =
+            serde_yaml::from_str("image: app:${TAG}\nports: [\"${PORT:-80}:80\"]").unwrap();
+        interpolate_value(&mut value, &resolver()).unwrap();
+        assert_eq!(value["image"], "app:from-set");
+        assert_eq!(value["ports"][0], "from-process:80");
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        let vars = parse_env_file("# comment\n\nA=1\nexport B=\"two words\"\nC = 3\nnot a var\n");
+        assert_eq!(vars.len(), 3);
+        assert_eq!(vars["A"], "1");
+        assert_eq!(vars["B"], "two words");
+        assert_eq!(vars["C"], "3");
+    }
+}
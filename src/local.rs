@@ -0,0 +1,68 @@
+use crate::{DownloadFile, YammerError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::path::PathBuf;
+
+/// A file on the local filesystem, e.g. one being worked on before it's pushed anywhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalFileSpec {
+    pub path: PathBuf,
+}
+
+impl LocalFileSpec {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+/// Reads files from the local filesystem.
+#[derive(Debug, Clone, Default)]
+pub struct LocalFileDownloader;
+
+impl LocalFileDownloader {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl DownloadFile for LocalFileDownloader {
+    type FileSpec = LocalFileSpec;
+
+    async fn download_file(&self, spec: &Self::FileSpec) -> Result<Bytes, YammerError> {
+        let contents = tokio::fs::read(&spec.path)
+            .await
+            .map_err(|source| YammerError::Io {
+                path: spec.path.clone(),
+                source,
+            })?;
+        Ok(contents.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[tokio::test]
+    async fn test_read_local_compose_file() {
+        let dir = TempDir::new("local");
+        let path = dir.join("docker-compose.yml");
+        std::fs::write(&path, "services:\n  redis:\n    image: redis\n").unwrap();
+        let downloader = LocalFileDownloader::new();
+
+        let compose_file = downloader
+            .download_compose_file(&LocalFileSpec::new(&path))
+            .await
+            .unwrap();
+        assert_eq!(compose_file.get_service("redis").unwrap()["image"], "redis");
+
+        std::fs::remove_file(&path).unwrap();
+        let err = downloader
+            .download_file(&LocalFileSpec::new(&path))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, YammerError::Io { .. }));
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{
+    ComposeServiceGithubSpec, ComposeServiceSpec, FieldPatch, FileSource, GithubRef,
+    SourceRegistry, YammerError, is_glob, parse_service_selections, validate_service_name,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A spec listed in a manifest: either just its DSN, its DSN with options, or a Github spec
+/// in its expanded form.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum ManifestEntry {
+    Dsn(String),
+    Detailed(DetailedEntry),
+    Github(ComposeServiceGithubSpec<String>),
+}
+
+/// A spec listed in a manifest along with options that would be awkward to spell in its DSN.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DetailedEntry {
+    pub dsn: String,
+    /// The branch to download the spec's files from, instead of the one in its DSN.
+    pub branch: Option<String>,
+    /// Services to select on top of the ones in the DSN.
+    #[serde(default)]
+    pub services: Vec<String>,
+    /// The names to merge selected services under, by their name in the source file.
+    #[serde(default)]
+    pub rename: BTreeMap<String, String>,
+    /// Changes to the fields of selected services, by their name in the source file. A field
+    /// set to `-` is removed.
+    #[serde(default)]
+    pub patch: BTreeMap<String, BTreeMap<String, serde_yaml::Value>>,
+}
+
+/// A file listing specs, so they can be versioned and reviewed instead of repeated on the
+/// command line, e.g.
+///
+/// ```yaml
+/// specs:
+///   - omnivore-app/omnivore+main:docker-compose.yml@redis
+///   - dsn: org/api:docker-compose.yml@api
+///     branch: release/2.0
+///     services: [worker]
+///     rename:
+///       api: backend
+///     patch:
+///       worker:
+///         image: org/worker:2.0
+///         ports: "-"
+///   - project: org
+///     repository: worker
+///     services: [worker]
+/// ```
+///
+/// or the same in TOML, with a `[[specs]]` table for each detailed entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub specs: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn parse_yaml(contents: &str) -> Result<Self, YammerError> {
+        serde_yaml::from_str(contents).map_err(|err| YammerError::InvalidManifest(err.to_string()))
+    }
+
+    pub fn parse_toml(contents: &str) -> Result<Self, YammerError> {
+        toml::from_str(contents).map_err(|err| YammerError::InvalidManifest(err.to_string()))
+    }
+
+    /// Read the manifest at `path`, as TOML if it has a `.toml` extension and YAML otherwise.
+    pub fn load(path: &Path) -> Result<Self, YammerError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| YammerError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("toml") => {
+                Self::parse_toml(&contents)
+            }
+            _ => Self::parse_yaml(&contents),
+        }
+    }
+
+    /// The specs the manifest lists, in order, with their options applied.
+    pub fn specs(&self, registry: &SourceRegistry) -> Result<Vec<ComposeServiceSpec>, YammerError> {
+        self.specs
+            .iter()
+            .map(|entry| match entry {
+                ManifestEntry::Dsn(dsn) => registry.parse(dsn),
+                ManifestEntry::Detailed(entry) => entry.spec(registry),
+                ManifestEntry::Github(spec) => Ok(spec.clone().into()),
+            })
+            .collect()
+    }
+}
+
+impl DetailedEntry {
+    fn spec(&self, registry: &SourceRegistry) -> Result<ComposeServiceSpec, YammerError> {
+        let mut spec = registry.parse(&self.dsn)?;
+        if let Some(branch) = &self.branch {
+            let sources = std::iter::once(&mut spec.source).chain(&mut spec.overrides);
+            for source in sources {
+                match source {
+                    FileSource::Github(file) => {
+                        file.branch = branch.clone();
+                        file.reference = GithubRef::Branch;
+                    }
+                    FileSource::Gitlab(file) => file.branch = branch.clone(),
+                    _ => {
+                        return Err(YammerError::InvalidManifest(format!(
+                            "`{}` can't be given a branch, only Github and GitLab specs can",
+                            self.dsn
+                        )));
+                    }
+                }
+            }
+        }
+        for selection in parse_service_selections(&self.services.join(","))? {
+            if !spec.services.contains(&selection) {
+                spec.services.push(selection);
+            }
+        }
+        for (source_name, target_name) in &self.rename {
+            let Some(selection) = spec
+                .services
+                .iter_mut()
+                .find(|selection| selection.source_name == *source_name)
+            else {
+                return Err(YammerError::InvalidManifest(format!(
+                    "`{}` renames `{source_name}`, which it doesn't select",
+                    self.dsn
+                )));
+            };
+            if is_glob(source_name) {
+                return Err(YammerError::InvalidManifest(format!(
+                    "`{}` renames `{source_name}`, a pattern that may match more than one service",
+                    self.dsn
+                )));
+            }
+            validate_service_name(target_name, false)?;
+            selection.target_name = target_name.clone();
+        }
+        for (source_name, fields) in &self.patch {
+            let Some(selection) = spec
+                .services
+                .iter_mut()
+                .find(|selection| selection.source_name == *source_name)
+            else {
+                return Err(YammerError::InvalidManifest(format!(
+                    "`{}` patches `{source_name}`, which it doesn't select",
+                    self.dsn
+                )));
+            };
+            if is_glob(source_name) {
+                return Err(YammerError::InvalidManifest(format!(
+                    "`{}` patches `{source_name}`, a pattern that may match more than one service",
+                    self.dsn
+                )));
+            }
+            for (field, value) in fields {
+                let patch = match value {
+                    serde_yaml::Value::String(value) if value == "-" => FieldPatch::remove(field),
+                    value => FieldPatch::set(field, serde_yaml::to_string(value)?.trim_end()),
+                };
+                selection.patches.push(patch);
+            }
+        }
+        Ok(spec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yaml_manifest() {
+        let manifest = Manifest::parse_yaml(
+            r#"
+            specs:
+              - omnivore-app/omnivore+main:docker-compose.yml@redis
+              - dsn: org/api:docker-compose.yml@api
+                branch: release/2.0
+                services: [worker]
+                rename:
+                  api: backend
+                patch:
+                  worker:
+                    image: org/worker:2.0
+                    ports: "-"
+              - project: org
+                repository: worker
+                services: [worker=jobs]"#,
+        )
+        .unwrap();
+        let specs = manifest.specs(&SourceRegistry::default()).unwrap();
+
+        assert_eq!(
+            specs[0].to_string(),
+            "omnivore-app/omnivore+main:docker-compose.yml@redis"
+        );
+        assert_eq!(
+            specs[1].to_string(),
+            "org/api+release/2.0:docker-compose.yml@api=backend,worker{image=org/worker:2.0,ports=-}"
+        );
+        assert_eq!(specs[2].to_string(), "org/worker@worker=jobs");
+    }
+
+    #[test]
+    fn test_toml_manifest() {
+        let manifest = Manifest::parse_toml(
+            r#"
+            [[specs]]
+            dsn = "gist:abc123:docker-compose.yml@redis"
+            rename = { redis = "cache" }
+            "#,
+        )
+        .unwrap();
+        let specs = manifest.specs(&SourceRegistry::default()).unwrap();
+        assert_eq!(specs[0].services[0].target_name, "cache");
+
+        let branched = Manifest::parse_toml(
+            "[[specs]]\ndsn = \"gist:abc123:docker-compose.yml@redis\"\nbranch = \"main\"\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            branched.specs(&SourceRegistry::default()),
+            Err(YammerError::InvalidManifest(_))
+        ));
+        assert!(Manifest::parse_toml("specs = [{ repo = \"org/repo\" }]").is_err());
+    }
+}
@@ -0,0 +1,1261 @@
+use crate::{
+    AnchorHoister, ComposeServiceSpec, DefinitionCollector, DockerComposeFile, SourceSummary,
+    SpecId, YammerError, is_glob, order_by_source, rename_references, serialize_millis,
+    service_comments, source_names,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// How a service is combined with an earlier definition of the same name,
+/// whether that came from another spec or from the existing output file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// The later definition replaces the earlier one wholesale.
+    #[default]
+    Replace,
+    /// Mappings are merged key by key, recursively. Anything else is replaced by the later value.
+    Deep,
+}
+
+impl MergeStrategy {
+    pub fn combine(
+        &self,
+        earlier: serde_yaml::Value,
+        later: serde_yaml::Value,
+    ) -> serde_yaml::Value {
+        match self {
+            MergeStrategy::Replace => later,
+            MergeStrategy::Deep => deep_merge(earlier, later),
+        }
+    }
+}
+
+impl FromStr for MergeStrategy {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "replace" => Ok(MergeStrategy::Replace),
+            "deep" => Ok(MergeStrategy::Deep),
+            _ => Err(YammerError::UnknownMergeStrategy(s.to_string())),
+        }
+    }
+}
+
+/// Which definition wins when the same service comes from more than one spec.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// The spec given later wins.
+    #[default]
+    Last,
+    /// The spec whose source file was changed most recently wins. Sources without a known
+    /// modification time fall back to [`ConflictPolicy::Last`].
+    Newest,
+    /// The spec given first wins, and later definitions are ignored.
+    Skip,
+    /// Refuse to merge the service.
+    Error,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "last" | "overwrite" => Ok(ConflictPolicy::Last),
+            "newest" => Ok(ConflictPolicy::Newest),
+            "skip" => Ok(ConflictPolicy::Skip),
+            "error" => Ok(ConflictPolicy::Error),
+            _ => Err(YammerError::UnknownConflictPolicy(s.to_string())),
+        }
+    }
+}
+
+/// What to do when two sources set the same `environment` variable of a service to different
+/// values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EnvConflictPolicy {
+    /// Keep the value from the earlier source.
+    First,
+    /// Keep the value from the later source.
+    #[default]
+    Last,
+    /// Refuse to merge the service.
+    Error,
+}
+
+impl FromStr for EnvConflictPolicy {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(EnvConflictPolicy::First),
+            "last" => Ok(EnvConflictPolicy::Last),
+            "error" => Ok(EnvConflictPolicy::Error),
+            _ => Err(YammerError::UnknownEnvConflictPolicy(s.to_string())),
+        }
+    }
+}
+
+/// An `environment` variable two sources disagreed on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EnvConflict {
+    pub service: String,
+    pub key: String,
+    pub earlier: Option<String>,
+    pub later: Option<String>,
+    /// The value that ended up in the merged service.
+    pub kept: Option<String>,
+}
+
+impl std::fmt::Display for EnvConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |value: &Option<String>| match value {
+            Some(value) => format!("`{value}`"),
+            None => "no value".to_string(),
+        };
+        write!(
+            f,
+            "service `{}` sets `{}` to both {} and {}, kept {}",
+            self.service,
+            self.key,
+            show(&self.earlier),
+            show(&self.later),
+            show(&self.kept)
+        )
+    }
+}
+
+/// What happened while merging services, for reporting back to the user.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MergeReport {
+    pub env_conflicts: Vec<EnvConflict>,
+    /// The specs that contributed to each service, in the order they were merged.
+    pub contributors: BTreeMap<String, Vec<SpecId>>,
+    /// How downloading and selecting from each spec's source went, in spec order.
+    pub sources: Vec<SourceSummary>,
+    /// The services of the existing output that `--prune` removed.
+    pub pruned: Vec<String>,
+    /// How long the run took, from the start of the downloads.
+    #[serde(rename = "elapsed_ms", serialize_with = "serialize_millis")]
+    pub elapsed: Option<Duration>,
+}
+
+impl MergeReport {
+    /// Follow the services being renamed after the merge.
+    pub fn rename_services(&mut self, rename: impl Fn(&str) -> String) {
+        self.contributors = std::mem::take(&mut self.contributors)
+            .into_iter()
+            .map(|(name, specs)| (rename(&name), specs))
+            .collect();
+    }
+
+    /// Forget about the services that didn't make it into the final `services`.
+    pub fn retain_services(&mut self, services: &serde_yaml::Mapping) {
+        self.contributors
+            .retain(|name, _| services.contains_key(name.as_str()));
+    }
+}
+
+/// Folds the services selected out of every spec into one set of services, keeping a
+/// [`MergeReport`] of how it went.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceMerger {
+    pub strategy: MergeStrategy,
+    pub env_conflict: EnvConflictPolicy,
+    pub on_conflict: ConflictPolicy,
+    services: serde_yaml::Mapping,
+    /// When the source of the winning definition of each service was last modified.
+    modified: HashMap<String, SystemTime>,
+    report: MergeReport,
+}
+
+impl ServiceMerger {
+    pub fn new(strategy: MergeStrategy, env_conflict: EnvConflictPolicy) -> Self {
+        Self {
+            strategy,
+            env_conflict,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_on_conflict(mut self, on_conflict: ConflictPolicy) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    /// Merge a service selected out of `spec` with any earlier definition of the same name.
+    pub fn add(
+        &mut self,
+        spec: &SpecId,
+        name: &str,
+        service: serde_yaml::Mapping,
+    ) -> Result<(), YammerError> {
+        self.add_modified(spec, name, service, None)
+    }
+
+    /// Like [`ServiceMerger::add`], for a service whose source was last modified at `modified`.
+    ///
+    /// With [`ConflictPolicy::Newest`], a definition from a source older than the one already
+    /// merged is treated as if it came first, so the newer one wins.
+    pub fn add_modified(
+        &mut self,
+        spec: &SpecId,
+        name: &str,
+        service: serde_yaml::Mapping,
+        modified: Option<SystemTime>,
+    ) -> Result<(), YammerError> {
+        let mut service = serde_yaml::Value::Mapping(service);
+        let takes_precedence = self.takes_precedence(name, modified);
+        match self.services.get_mut(name) {
+            Some(_) if self.on_conflict == ConflictPolicy::Skip => return Ok(()),
+            Some(_) if self.on_conflict == ConflictPolicy::Error => {
+                let earlier = self.report.contributors.get(name).into_iter().flatten();
+                return Err(YammerError::ServiceConflict {
+                    name: name.to_string(),
+                    sources: earlier.chain([spec]).map(ToString::to_string).collect(),
+                });
+            }
+            Some(earlier) => {
+                if !takes_precedence {
+                    let mut newer = std::mem::take(earlier);
+                    self.env_conflict
+                        .reconcile(name, &service, &mut newer, &mut self.report)?;
+                    *earlier = self.strategy.combine(service, newer);
+                } else {
+                    self.env_conflict
+                        .reconcile(name, earlier, &mut service, &mut self.report)?;
+                    *earlier = self.strategy.combine(std::mem::take(earlier), service);
+                    if let Some(modified) = modified {
+                        self.modified.insert(name.to_string(), modified);
+                    }
+                }
+            }
+            None => {
+                self.services.insert(name.into(), service);
+                if let Some(modified) = modified {
+                    self.modified.insert(name.to_string(), modified);
+                }
+            }
+        }
+        self.report
+            .contributors
+            .entry(name.to_string())
+            .or_default()
+            .push(spec.clone());
+        Ok(())
+    }
+
+    /// Whether a definition of `name` from a source last modified at `modified` would win over
+    /// the one already merged, wherever the two disagree. A first definition always does.
+    pub fn takes_precedence(&self, name: &str, modified: Option<SystemTime>) -> bool {
+        if !self.services.contains_key(name) {
+            return true;
+        }
+        match self.on_conflict {
+            ConflictPolicy::Last => true,
+            ConflictPolicy::Newest => !matches!(
+                (self.modified.get(name), modified),
+                (Some(earlier), Some(later)) if later < *earlier
+            ),
+            ConflictPolicy::Skip | ConflictPolicy::Error => false,
+        }
+    }
+
+    pub fn services(&self) -> &serde_yaml::Mapping {
+        &self.services
+    }
+
+    pub fn report(&self) -> &MergeReport {
+        &self.report
+    }
+
+    pub fn into_parts(self) -> (serde_yaml::Mapping, MergeReport) {
+        (self.services, self.report)
+    }
+}
+
+/// The compose versions the merged sources declare, each once, in the order they were seen.
+///
+/// Compose files don't need a `version` anymore, so merged output only gets one if some
+/// source declared it, and then the first one declared.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeclaredVersions(Vec<String>);
+
+impl DeclaredVersions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note the version a source declares, if it declares one.
+    pub fn add(&mut self, version: Option<&str>) {
+        if let Some(version) = version
+            && !self.0.iter().any(|seen| seen == version)
+        {
+            self.0.push(version.to_string());
+        }
+    }
+
+    /// The version to give the merged output.
+    pub fn merged(&self) -> Option<&str> {
+        self.0.first().map(String::as_str)
+    }
+
+    /// A warning about the sources declaring different versions, if they do.
+    pub fn conflict(&self) -> Option<String> {
+        (self.0.len() > 1).then(|| {
+            format!(
+                "sources declare different compose versions ({}), using `{}`",
+                self.0.join(", "),
+                self.0[0]
+            )
+        })
+    }
+}
+
+/// The `version` to give merged output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ComposeVersion {
+    /// The first version the sources declare, if any do, see [`DeclaredVersions`].
+    #[default]
+    Declared,
+    /// No `version` at all, which the Compose Specification doesn't need and the compose CLI
+    /// warns about.
+    Omitted,
+    /// This version, whatever the sources declare, for tooling that still wants one.
+    Pinned(String),
+}
+
+impl ComposeVersion {
+    /// The version to give the merged output of sources that declared `declared`.
+    pub fn resolve<'a>(&'a self, declared: &'a DeclaredVersions) -> Option<&'a str> {
+        match self {
+            ComposeVersion::Declared => declared.merged(),
+            ComposeVersion::Omitted => None,
+            ComposeVersion::Pinned(version) => Some(version),
+        }
+    }
+}
+
+impl FromStr for ComposeVersion {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "declared" => Ok(ComposeVersion::Declared),
+            "none" => Ok(ComposeVersion::Omitted),
+            version if !version.is_empty() && !version.contains(char::is_whitespace) => {
+                Ok(ComposeVersion::Pinned(version.to_string()))
+            }
+            _ => Err(YammerError::UnknownComposeVersion(s.to_string())),
+        }
+    }
+}
+
+/// A service selected out of a spec's file by [`FileMerger::select`], ready to be merged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectedService {
+    /// The name the file defines it under.
+    pub name: String,
+    /// The name it's merged under.
+    pub target_name: String,
+    /// Its definition, with its references renamed and the spec's patches applied.
+    pub contents: serde_yaml::Mapping,
+    /// The comments written above it in the file.
+    pub comments: Vec<String>,
+}
+
+/// What merging every spec's file came to: see [`FileMerger::finish`].
+#[derive(Debug, Clone, Default)]
+pub struct MergedFiles {
+    /// The merged top-level contents, with the services under `services`.
+    pub contents: serde_yaml::Mapping,
+    pub report: MergeReport,
+    /// The comments of each merged service, by the name it's merged under.
+    pub comments: BTreeMap<String, Vec<String>>,
+    pub warnings: Vec<String>,
+}
+
+/// Merges the services each spec selects out of its file into one set of top-level contents,
+/// along with the `x-` extensions the specs select and the top-level definitions the selected
+/// services refer to.
+///
+/// Each file is first run through [`FileMerger::select`], whose services can be changed
+/// further before they're merged with [`FileMerger::add`].
+#[derive(Debug, Clone, Default)]
+pub struct FileMerger {
+    services: ServiceMerger,
+    compose_version: ComposeVersion,
+    with_dependencies: bool,
+    with_dependents: bool,
+    source_order: bool,
+    hoist_anchors: bool,
+    require_services: bool,
+    versions: DeclaredVersions,
+    definitions: DefinitionCollector,
+    hoister: AnchorHoister,
+    extensions: serde_yaml::Mapping,
+    comments: BTreeMap<String, Vec<String>>,
+}
+
+impl FileMerger {
+    pub fn new(services: ServiceMerger) -> Self {
+        Self {
+            services,
+            ..Default::default()
+        }
+    }
+
+    /// The `version` to give the merged contents. See [`ComposeVersion`].
+    pub fn with_compose_version(mut self, compose_version: ComposeVersion) -> Self {
+        self.compose_version = compose_version;
+        self
+    }
+
+    /// Also select the services the selected ones depend on, transitively.
+    pub fn with_dependencies(mut self, with_dependencies: bool) -> Self {
+        self.with_dependencies = with_dependencies;
+        self
+    }
+
+    /// Also select the services that depend on the selected ones.
+    pub fn with_dependents(mut self, with_dependents: bool) -> Self {
+        self.with_dependents = with_dependents;
+        self
+    }
+
+    /// Select each file's services in the order the file defines them, rather than the order
+    /// the spec names them in.
+    pub fn with_source_order(mut self, source_order: bool) -> Self {
+        self.source_order = source_order;
+        self
+    }
+
+    /// Carry the anchored `x-` blocks the selected services use over to the top level. See
+    /// [`AnchorHoister`].
+    pub fn with_hoisted_anchors(mut self, hoist_anchors: bool) -> Self {
+        self.hoist_anchors = hoist_anchors;
+        self
+    }
+
+    /// Fail with [`YammerError::ServiceNotFound`] when a spec names a service its file doesn't
+    /// define, rather than warning about it.
+    pub fn with_required_services(mut self, require_services: bool) -> Self {
+        self.require_services = require_services;
+        self
+    }
+
+    /// Select the services `spec` names out of `compose_file`, the file downloaded for it.
+    ///
+    /// Patterns are expanded, selected `x-` extensions are set aside to be merged as top-level
+    /// keys, and the definitions the services refer to are collected. Each selected service
+    /// comes with its references renamed and the spec's patches applied. Returns the selected
+    /// services along with warnings about anything that couldn't be selected cleanly.
+    pub fn select(
+        &mut self,
+        spec: &ComposeServiceSpec,
+        compose_file: &DockerComposeFile,
+    ) -> Result<(Vec<SelectedService>, Vec<String>), YammerError> {
+        let source = &spec.source;
+        let origin = source.to_string();
+        let mut warnings = vec![];
+        let mut names: Vec<String> = vec![];
+        for name in source_names(&spec.services) {
+            let matched = if is_glob(&name) {
+                let matched = compose_file.matching_services(&name);
+                if matched.is_empty() {
+                    warnings.push(format!("`{name}` doesn't match any service in {source}"));
+                }
+                matched
+            } else {
+                vec![name]
+            };
+            for name in matched {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        if self.with_dependencies {
+            names = compose_file.resolve_dependencies(&names);
+        }
+        if self.with_dependents {
+            for name in names.clone() {
+                for dependent in compose_file.reverse_dependencies(&name) {
+                    if !names.contains(&dependent) {
+                        names.push(dependent);
+                    }
+                }
+            }
+        }
+
+        self.versions.add(compose_file.version.as_deref());
+        if self.hoist_anchors {
+            warnings.extend(self.hoister.hoist(compose_file, &names, &origin));
+        }
+        warnings.extend(self.definitions.collect(compose_file, &names, &origin));
+
+        let names = if self.source_order {
+            order_by_source(&names, compose_file)
+        } else {
+            names.iter().collect()
+        };
+        let mut selected = vec![];
+        for name in names {
+            let target_name = spec.target_name(name);
+            // Selected `x-` extensions go to the top level, as they are.
+            if compose_file.get_service(name).is_none()
+                && let Some(extension) = compose_file.get_extension(name)
+            {
+                match self.extensions.get(target_name.as_str()) {
+                    Some(existing) if existing != extension => warnings.push(format!(
+                        "`{target_name}` from {source} differs from the one already selected, keeping the first"
+                    )),
+                    _ => {
+                        self.extensions
+                            .insert(target_name.into(), extension.clone());
+                    }
+                }
+                continue;
+            }
+            let mut contents = match compose_file.require_service(name, source) {
+                Ok(contents) => contents.clone(),
+                Err(err) if !self.require_services => {
+                    warnings.push(err.to_string());
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            rename_references(&mut contents, &|name| spec.target_name(name));
+            if let Some(selection) = spec.selection(name) {
+                selection.apply_patches(&mut contents)?;
+            }
+            let comments = compose_file
+                .raw
+                .as_deref()
+                .map(|text| service_comments(text, name))
+                .unwrap_or_default();
+            selected.push(SelectedService {
+                name: name.clone(),
+                target_name,
+                contents,
+                comments,
+            });
+        }
+        Ok((selected, warnings))
+    }
+
+    /// Merge a service selected out of the file of `spec` with any earlier definition of the
+    /// same name, like [`ServiceMerger::add_modified`] does.
+    ///
+    /// The merged service keeps the comments of whichever definition wins.
+    pub fn add(
+        &mut self,
+        spec: &SpecId,
+        service: SelectedService,
+        modified: Option<SystemTime>,
+    ) -> Result<(), YammerError> {
+        let takes_precedence = self
+            .services
+            .takes_precedence(&service.target_name, modified);
+        self.services
+            .add_modified(spec, &service.target_name, service.contents, modified)?;
+        if takes_precedence {
+            if service.comments.is_empty() {
+                self.comments.remove(&service.target_name);
+            } else {
+                self.comments.insert(service.target_name, service.comments);
+            }
+        }
+        Ok(())
+    }
+
+    /// The merged contents: the hoisted anchors, the selected `x-` extensions, the collected
+    /// definitions, the services and the version, in that order.
+    pub fn finish(self) -> MergedFiles {
+        let mut warnings = vec![];
+        if self.compose_version == ComposeVersion::Declared
+            && let Some(conflict) = self.versions.conflict()
+        {
+            warnings.push(conflict);
+        }
+        let (services, report) = self.services.into_parts();
+        let mut contents = self.hoister.into_mapping();
+        contents.extend(self.extensions);
+        for (key, section) in self.definitions.into_sections() {
+            contents.insert(key.into(), serde_yaml::Value::Mapping(section));
+        }
+        contents.insert("services".into(), serde_yaml::Value::Mapping(services));
+        if let Some(version) = self.compose_version.resolve(&self.versions) {
+            contents.insert("version".into(), version.into());
+        }
+        MergedFiles {
+            contents,
+            report,
+            comments: self.comments,
+            warnings,
+        }
+    }
+}
+
+/// Select the services each spec names out of the file downloaded for it, and merge them, in
+/// order, into a single compose file, without downloading anything or touching the disk.
+///
+/// Files are merged with a default [`FileMerger`]. Returns the merged file along with the
+/// warnings merging it raised, like services a file doesn't define.
+pub fn merge_services(
+    files: &[(ComposeServiceSpec, DockerComposeFile)],
+) -> Result<(DockerComposeFile, Vec<String>), YammerError> {
+    let mut merger = FileMerger::default();
+    let mut warnings = vec![];
+    for (index, (spec, compose_file)) in files.iter().enumerate() {
+        let (services, selection_warnings) = merger.select(spec, compose_file)?;
+        warnings.extend(selection_warnings);
+        for service in services {
+            merger.add(&spec.id(index), service, None)?;
+        }
+    }
+    let mut merged = merger.finish();
+    warnings.extend(merged.warnings);
+    let version = merged.contents.remove("version");
+    let services = merged.contents.remove("services");
+    Ok((
+        DockerComposeFile {
+            version: version.and_then(|version| version.as_str().map(str::to_string)),
+            services: services.and_then(|services| services.as_mapping().cloned()),
+            other: merged.contents,
+            raw: None,
+        },
+        warnings,
+    ))
+}
+
+/// The `KEY=VALUE` entries of a service's `environment`, in either the list or mapping form.
+fn environment_entries(service: &serde_yaml::Value) -> Vec<(String, Option<String>)> {
+    fn scalar(value: &serde_yaml::Value) -> Option<String> {
+        match value {
+            serde_yaml::Value::String(value) => Some(value.clone()),
+            serde_yaml::Value::Number(value) => Some(value.to_string()),
+            serde_yaml::Value::Bool(value) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    match service.get("environment") {
+        Some(serde_yaml::Value::Mapping(environment)) => environment
+            .iter()
+            .filter_map(|(key, value)| Some((key.as_str()?.to_string(), scalar(value))))
+            .collect(),
+        Some(serde_yaml::Value::Sequence(environment)) => environment
+            .iter()
+            .filter_map(serde_yaml::Value::as_str)
+            .map(|entry| match entry.split_once('=') {
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                None => (entry.to_string(), None),
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Overwrite `key` in a service's `environment`, keeping whichever form it is written in.
+fn set_environment_entry(service: &mut serde_yaml::Value, key: &str, value: Option<&str>) {
+    match service.get_mut("environment") {
+        Some(serde_yaml::Value::Mapping(environment)) => {
+            environment.insert(
+                key.into(),
+                value.map_or(serde_yaml::Value::Null, Into::into),
+            );
+        }
+        Some(serde_yaml::Value::Sequence(environment)) => {
+            let entry = match value {
+                Some(value) => format!("{key}={value}"),
+                None => key.to_string(),
+            };
+            for existing in environment.iter_mut() {
+                let Some(existing_key) =
+                    existing.as_str().map(|e| e.split('=').next().unwrap_or(e))
+                else {
+                    continue;
+                };
+                if existing_key == key {
+                    *existing = entry.clone().into();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+impl EnvConflictPolicy {
+    /// Settle every `environment` variable `earlier` and `later` set differently, by rewriting
+    /// `later` so that combining the two keeps the value this policy picks.
+    ///
+    /// Each conflict is recorded in `report`. With [`EnvConflictPolicy::Error`] the first
+    /// conflict is returned as a [`YammerError::EnvConflict`] instead.
+    pub fn reconcile(
+        &self,
+        service: &str,
+        earlier: &serde_yaml::Value,
+        later: &mut serde_yaml::Value,
+        report: &mut MergeReport,
+    ) -> Result<(), YammerError> {
+        let earlier_entries = environment_entries(earlier);
+        for (key, later_value) in environment_entries(later) {
+            let Some((_, earlier_value)) = earlier_entries.iter().find(|(k, _)| *k == key) else {
+                continue;
+            };
+            if *earlier_value == later_value {
+                continue;
+            }
+            let kept = match self {
+                EnvConflictPolicy::First => {
+                    set_environment_entry(later, &key, earlier_value.as_deref());
+                    earlier_value.clone()
+                }
+                EnvConflictPolicy::Last => later_value.clone(),
+                EnvConflictPolicy::Error => {
+                    return Err(YammerError::EnvConflict {
+                        service: service.to_string(),
+                        key,
+                    });
+                }
+            };
+            report.env_conflicts.push(EnvConflict {
+                service: service.to_string(),
+                key,
+                earlier: earlier_value.clone(),
+                later: later_value,
+                kept,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Recursively merge `later` into `earlier`, with `later` winning on anything that isn't a mapping.
+pub fn deep_merge(earlier: serde_yaml::Value, later: serde_yaml::Value) -> serde_yaml::Value {
+    deep_merge_with(earlier, later, &|_, later| later)
+}
+
+/// Like [`deep_merge`], with sequences at the same place in both combined by `sequences`.
+pub fn deep_merge_with(
+    earlier: serde_yaml::Value,
+    later: serde_yaml::Value,
+    sequences: &impl Fn(Vec<serde_yaml::Value>, Vec<serde_yaml::Value>) -> Vec<serde_yaml::Value>,
+) -> serde_yaml::Value {
+    match (earlier, later) {
+        (serde_yaml::Value::Mapping(mut earlier), serde_yaml::Value::Mapping(later)) => {
+            for (key, value) in later {
+                match earlier.get_mut(&key) {
+                    Some(existing) => {
+                        *existing = deep_merge_with(std::mem::take(existing), value, sequences)
+                    }
+                    None => {
+                        earlier.insert(key, value);
+                    }
+                }
+            }
+            serde_yaml::Value::Mapping(earlier)
+        }
+        (serde_yaml::Value::Sequence(earlier), serde_yaml::Value::Sequence(later)) => {
+            serde_yaml::Value::Sequence(sequences(earlier, later))
+        }
+        (_, later) => later,
+    }
+}
+
+/// The top-level sections that map names to definitions, and are merged name by name.
+const DEFINITION_SECTIONS: [&str; 4] = ["networks", "volumes", "configs", "secrets"];
+
+/// Merge freshly composed top-level contents on top of an existing docker compose file.
+///
+/// Every top-level key of `existing` is kept, including ones the composed contents don't have
+/// (like hand-written `networks` or `x-` blocks). Services already present in `existing` are
+/// kept, and combined with any composed service of the same name according to `strategy`.
+/// Composed `networks`, `volumes`, `configs` and `secrets` are added to the existing ones name
+/// by name, replacing those of the same name, and every other composed top-level key
+/// overwrites the existing one. The existing `version` is always replaced by the composed one,
+/// which a [`FileMerger`] resolves from its [`ComposeVersion`], so a version the sources no
+/// longer declare (or that's been asked to be left out) doesn't linger from an earlier run.
+///
+/// The top-level keys come out in alphabetical order, and the services in the order they were
+/// first seen, so the same inputs always serialize to the same output.
+pub fn merge_with_existing(
+    existing: Option<&str>,
+    composed: serde_yaml::Mapping,
+    strategy: MergeStrategy,
+) -> Result<serde_yaml::Mapping, YammerError> {
+    let mut all_contents: serde_yaml::Mapping = match existing {
+        Some(contents) => match serde_yaml::from_str(contents)? {
+            serde_yaml::Value::Null => serde_yaml::Mapping::new(),
+            value => serde_yaml::from_value(value)?,
+        },
+        None => serde_yaml::Mapping::new(),
+    };
+    all_contents.remove("version");
+    let mut services = match all_contents.remove("services") {
+        Some(serde_yaml::Value::Mapping(services)) => services,
+        _ => serde_yaml::Mapping::new(),
+    };
+
+    for (key, value) in composed {
+        match (key.as_str(), value) {
+            (Some("services"), serde_yaml::Value::Mapping(composed_services)) => {
+                for (name, service) in composed_services {
+                    match services.get_mut(&name) {
+                        Some(existing) => {
+                            *existing = strategy.combine(std::mem::take(existing), service)
+                        }
+                        None => {
+                            services.insert(name, service);
+                        }
+                    }
+                }
+            }
+            (Some(section), serde_yaml::Value::Mapping(definitions))
+                if DEFINITION_SECTIONS.contains(&section) =>
+            {
+                match all_contents.get_mut(&key) {
+                    Some(serde_yaml::Value::Mapping(existing)) => existing.extend(definitions),
+                    _ => {
+                        all_contents.insert(key, serde_yaml::Value::Mapping(definitions));
+                    }
+                }
+            }
+            (_, value) => {
+                all_contents.insert(key, value);
+            }
+        }
+    }
+    all_contents.insert("services".into(), serde_yaml::Value::Mapping(services));
+    Ok(sort_top_level(all_contents))
+}
+
+/// The top-level contents with their keys in alphabetical order.
+fn sort_top_level(contents: serde_yaml::Mapping) -> serde_yaml::Mapping {
+    let mut entries: Vec<(serde_yaml::Value, serde_yaml::Value)> = contents.into_iter().collect();
+    entries.sort_by(|(a, _), (b, _)| a.as_str().cmp(&b.as_str()));
+    entries.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_with_existing_keeps_existing_services() {
+        let existing = r#"
+services:
+  redis:
+    image: redis
+"#;
+        let mut composed = serde_yaml::Mapping::new();
+        let services: serde_yaml::Mapping = serde_yaml::from_str("mongo:\n  image: mongo").unwrap();
+        composed.insert("services".into(), serde_yaml::Value::Mapping(services));
+        composed.insert("version".into(), "3".into());
+
+        let merged = merge_with_existing(Some(existing), composed, MergeStrategy::Replace).unwrap();
+        let services = merged["services"].as_mapping().unwrap();
+        assert!(services.contains_key("redis"));
+        assert!(services.contains_key("mongo"));
+        assert_eq!(merged["version"], "3");
+    }
+
+    #[test]
+    fn test_merge_with_existing_keeps_other_top_level_keys() {
+        let existing = r#"
+version: '3.8'
+x-owner: platform
+networks:
+  backend:
+    driver: overlay
+  cache: {}
+services:
+  redis:
+    image: redis
+    networks: [backend]
+"#;
+        let composed: serde_yaml::Mapping = serde_yaml::from_str(
+            "networks:\n  cache:\n    internal: true\nservices:\n  redis:\n    image: redis:7\n  mongo:\n    image: mongo\n",
+        )
+        .unwrap();
+
+        let merged = merge_with_existing(Some(existing), composed, MergeStrategy::Replace).unwrap();
+        // The composed contents don't declare a version, so the existing one doesn't last.
+        assert!(!merged.contains_key("version"));
+        assert_eq!(merged["x-owner"], "platform");
+        assert_eq!(merged["networks"]["backend"]["driver"], "overlay");
+        assert_eq!(merged["networks"]["cache"]["internal"], true);
+        assert_eq!(merged["services"]["redis"]["image"], "redis:7");
+        assert!(merged["services"]["mongo"].is_mapping());
+
+        // Merging the same contents again changes nothing.
+        let round_tripped = merge_with_existing(
+            Some(&serde_yaml::to_string(&merged).unwrap()),
+            serde_yaml::Mapping::new(),
+            MergeStrategy::Replace,
+        )
+        .unwrap();
+        assert_eq!(round_tripped, merged);
+        assert!(
+            merge_with_existing(Some(""), serde_yaml::Mapping::new(), MergeStrategy::Replace)
+                .is_ok()
+        );
+        assert!(
+            merge_with_existing(
+                Some("- a list"),
+                serde_yaml::Mapping::new(),
+                MergeStrategy::Replace
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_merge_services_without_downloading() {
+        let parse = |yaml: &str| DockerComposeFile::try_from(&bytes::Bytes::from(yaml.to_string()));
+        let files = [
+            (
+                "org/cache:docker-compose.yml@redis".parse().unwrap(),
+                parse(
+                    "services:\n  redis:\n    image: redis:6\n    networks: [cache]\n  mongo:\n    image: mongo\nnetworks:\n  cache: {}\n",
+                )
+                .unwrap(),
+            ),
+            (
+                "org/api:docker-compose.yml@redis,api".parse().unwrap(),
+                parse("version: '3'\nservices:\n  redis:\n    image: redis:7\n  api:\n    image: api\n")
+                    .unwrap(),
+            ),
+            (
+                "org/web:docker-compose.yml@web,missing".parse().unwrap(),
+                parse("version: '2'\nservices:\n  web:\n    image: web\n").unwrap(),
+            ),
+        ];
+
+        let (merged, warnings) = merge_services(&files).unwrap();
+
+        assert_eq!(merged.version.as_deref(), Some("3"));
+        let services: Vec<&str> = merged
+            .services
+            .as_ref()
+            .unwrap()
+            .keys()
+            .filter_map(|name| name.as_str())
+            .collect();
+        assert_eq!(services, vec!["redis", "api", "web"]);
+        assert_eq!(merged.get_service("redis").unwrap()["image"], "redis:7");
+        assert!(merged.other["networks"]["cache"].is_mapping());
+        assert_eq!(
+            warnings,
+            [
+                "No service `missing` in https://raw.githubusercontent.com/org/web/HEAD/docker-compose.yml",
+                "sources declare different compose versions (3, 2), using `3`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comments_follow_the_winning_definition() {
+        let files: [(ComposeServiceSpec, DockerComposeFile); 2] = [
+            (
+                "org/a:docker-compose.yml@redis".parse().unwrap(),
+                DockerComposeFile::try_from(&bytes::Bytes::from(
+                    "services:\n  # the first\n  redis:\n    image: redis:6\n",
+                ))
+                .unwrap(),
+            ),
+            (
+                "org/b:docker-compose.yml@redis".parse().unwrap(),
+                DockerComposeFile::try_from(&bytes::Bytes::from(
+                    "services:\n  # the second\n  redis:\n    image: redis:7\n",
+                ))
+                .unwrap(),
+            ),
+        ];
+        let merge = |on_conflict| {
+            let mut merger =
+                FileMerger::new(ServiceMerger::default().with_on_conflict(on_conflict));
+            for (index, (spec, compose_file)) in files.iter().enumerate() {
+                let (services, _) = merger.select(spec, compose_file).unwrap();
+                for service in services {
+                    merger.add(&spec.id(index), service, None).unwrap();
+                }
+            }
+            merger.finish()
+        };
+
+        let last = merge(ConflictPolicy::Last);
+        assert_eq!(last.contents["services"]["redis"]["image"], "redis:7");
+        assert_eq!(last.comments["redis"], ["# the second"]);
+        let skip = merge(ConflictPolicy::Skip);
+        assert_eq!(skip.contents["services"]["redis"]["image"], "redis:6");
+        assert_eq!(skip.comments["redis"], ["# the first"]);
+    }
+
+    #[test]
+    fn test_merge_services_selects_extensions() {
+        let files = [(
+            "org/repo:docker-compose.yml@api,x-common-env"
+                .parse()
+                .unwrap(),
+            DockerComposeFile::try_from(&bytes::Bytes::from(
+                "x-common-env:\n  TZ: UTC\nservices:\n  api:\n    image: api\n",
+            ))
+            .unwrap(),
+        )];
+
+        let (merged, warnings) = merge_services(&files).unwrap();
+
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(merged.other["x-common-env"]["TZ"], "UTC");
+        assert_eq!(merged.service_names().collect::<Vec<_>>(), ["api"]);
+    }
+
+    #[test]
+    fn test_file_merger_reports_the_spec_each_service_came_from() {
+        let parse = |yaml: &str| DockerComposeFile::try_from(&bytes::Bytes::from(yaml.to_string()));
+        let first: ComposeServiceSpec = "org/a:docker-compose.yml@db=postgres".parse().unwrap();
+        let second: ComposeServiceSpec = "org/b:docker-compose.yml@postgres".parse().unwrap();
+        let mut merger = FileMerger::default().with_required_services(true);
+
+        let (selected, warnings) = merger
+            .select(
+                &first,
+                &parse("services:\n  db:\n    image: postgres:15\n").unwrap(),
+            )
+            .unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(selected[0].target_name, "postgres");
+        for service in selected {
+            merger.add(&first.id(0), service, None).unwrap();
+        }
+        let (selected, _) = merger
+            .select(
+                &second,
+                &parse("services:\n  postgres:\n    image: postgres:16\n").unwrap(),
+            )
+            .unwrap();
+        for service in selected {
+            merger.add(&second.id(1), service, None).unwrap();
+        }
+        assert!(matches!(
+            merger.select(&second, &parse("services: {}\n").unwrap()),
+            Err(YammerError::ServiceNotFound { .. })
+        ));
+
+        let merged = merger.finish();
+        assert_eq!(
+            merged.contents["services"]["postgres"]["image"],
+            "postgres:16"
+        );
+        assert_eq!(
+            merged.report.contributors["postgres"],
+            [first.id(0), second.id(1)]
+        );
+    }
+
+    #[test]
+    fn test_declared_versions() {
+        let mut versions = DeclaredVersions::new();
+        versions.add(None);
+        assert_eq!(versions.merged(), None);
+        versions.add(Some("3"));
+        versions.add(Some("3"));
+        assert_eq!(versions.conflict(), None);
+        versions.add(Some("2"));
+        assert_eq!(versions.merged(), Some("3"));
+        assert_eq!(
+            versions.conflict().unwrap(),
+            "sources declare different compose versions (3, 2), using `3`"
+        );
+    }
+
+    #[test]
+    fn test_compose_version() {
+        let mut declared = DeclaredVersions::new();
+        declared.add(Some("2.4"));
+        let resolve = |version: &str| {
+            let version: ComposeVersion = version.parse().unwrap();
+            version.resolve(&declared).map(str::to_string)
+        };
+        assert_eq!(resolve("declared").as_deref(), Some("2.4"));
+        assert_eq!(resolve("none"), None);
+        assert_eq!(resolve("3.8").as_deref(), Some("3.8"));
+        assert_eq!(
+            ComposeVersion::default().resolve(&DeclaredVersions::new()),
+            None
+        );
+        assert!("".parse::<ComposeVersion>().is_err());
+        assert!("3 8".parse::<ComposeVersion>().is_err());
+    }
+
+    #[test]
+    fn test_merge_strategy_combine() {
+        let earlier: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            image: redis:6
+            environment:
+              A: "1"
+              B: "1"
+            ports: ["6379:6379"]"#,
+        )
+        .unwrap();
+        let later: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            image: redis:7
+            environment:
+              B: "2"
+            ports: ["6380:6379"]"#,
+        )
+        .unwrap();
+
+        let replaced = MergeStrategy::Replace.combine(earlier.clone(), later.clone());
+        assert_eq!(replaced, later);
+
+        let deep = MergeStrategy::Deep.combine(earlier, later);
+        assert_eq!(deep["image"], "redis:7");
+        assert_eq!(deep["environment"]["A"], "1");
+        assert_eq!(deep["environment"]["B"], "2");
+        assert_eq!(deep["ports"][0], "6380:6379");
+    }
+
+    #[test]
+    fn test_merge_strategy_from_str() {
+        assert_eq!(
+            "replace".parse::<MergeStrategy>().unwrap(),
+            MergeStrategy::Replace
+        );
+        assert_eq!(
+            "deep".parse::<MergeStrategy>().unwrap(),
+            MergeStrategy::Deep
+        );
+        assert!("shallow".parse::<MergeStrategy>().is_err());
+    }
+
+    fn log_level_sources() -> (serde_yaml::Value, serde_yaml::Value) {
+        let earlier = serde_yaml::from_str("environment:\n  LOG_LEVEL: debug\n  A: '1'").unwrap();
+        let later = serde_yaml::from_str("environment: [LOG_LEVEL=info, A=1]").unwrap();
+        (earlier, later)
+    }
+
+    #[test]
+    fn test_env_conflict_policies() {
+        for (policy, kept) in [
+            (EnvConflictPolicy::First, "debug"),
+            (EnvConflictPolicy::Last, "info"),
+        ] {
+            let (earlier, mut later) = log_level_sources();
+            let mut report = MergeReport::default();
+            policy
+                .reconcile("api", &earlier, &mut later, &mut report)
+                .unwrap();
+
+            assert_eq!(
+                report.env_conflicts,
+                vec![EnvConflict {
+                    service: "api".to_string(),
+                    key: "LOG_LEVEL".to_string(),
+                    earlier: Some("debug".to_string()),
+                    later: Some("info".to_string()),
+                    kept: Some(kept.to_string()),
+                }]
+            );
+            let merged = MergeStrategy::Deep.combine(earlier, later);
+            assert_eq!(
+                environment_entries(&merged)
+                    .into_iter()
+                    .find(|(key, _)| key == "LOG_LEVEL"),
+                Some(("LOG_LEVEL".to_string(), Some(kept.to_string())))
+            );
+        }
+
+        let (earlier, mut later) = log_level_sources();
+        let mut report = MergeReport::default();
+        let result = EnvConflictPolicy::Error.reconcile("api", &earlier, &mut later, &mut report);
+        assert!(
+            matches!(result, Err(YammerError::EnvConflict { ref key, .. }) if key == "LOG_LEVEL"),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_env_conflict_policy_from_str() {
+        assert_eq!(
+            "first".parse::<EnvConflictPolicy>().unwrap(),
+            EnvConflictPolicy::First
+        );
+        assert!("newest".parse::<EnvConflictPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_service_merger_records_contributors() {
+        let first = SpecId {
+            index: 0,
+            spec: "org/a:docker-compose.yml@redis,api".to_string(),
+        };
+        let second = SpecId {
+            index: 1,
+            spec: "org/b:docker-compose.yml@redis".to_string(),
+        };
+        let service = |yaml: &str| serde_yaml::from_str::<serde_yaml::Mapping>(yaml).unwrap();
+        let mut merger = ServiceMerger::new(MergeStrategy::Deep, EnvConflictPolicy::Last);
+
+        merger
+            .add(&first, "redis", service("image: redis:6"))
+            .unwrap();
+        merger.add(&first, "api", service("image: api")).unwrap();
+        merger
+            .add(&second, "redis", service("command: redis-server"))
+            .unwrap();
+
+        assert_eq!(merger.services()["redis"]["image"], "redis:6");
+        assert_eq!(merger.services()["redis"]["command"], "redis-server");
+        let (_, mut report) = merger.into_parts();
+        assert_eq!(report.contributors["redis"], vec![first.clone(), second]);
+        assert_eq!(report.contributors["api"], vec![first]);
+
+        report.rename_services(|name| format!("dev-{name}"));
+        assert!(report.contributors.contains_key("dev-redis"));
+    }
+
+    #[test]
+    fn test_conflict_policies_skip_and_error() {
+        let spec = |index: usize| SpecId {
+            index,
+            spec: format!("org/repo{index}:docker-compose.yml@redis"),
+        };
+        let service = |image: &str| {
+            let mut service = serde_yaml::Mapping::new();
+            service.insert("image".into(), image.into());
+            service
+        };
+
+        let mut skip = ServiceMerger::default().with_on_conflict(ConflictPolicy::Skip);
+        skip.add(&spec(0), "redis", service("redis:6")).unwrap();
+        skip.add(&spec(1), "redis", service("redis:7")).unwrap();
+        assert_eq!(skip.services()["redis"]["image"], "redis:6");
+        assert_eq!(skip.report().contributors["redis"], vec![spec(0)]);
+
+        let mut error = ServiceMerger::default().with_on_conflict(ConflictPolicy::Error);
+        error.add(&spec(0), "redis", service("redis:6")).unwrap();
+        let err = error
+            .add(&spec(1), "redis", service("redis:7"))
+            .unwrap_err();
+        assert!(matches!(
+            &err,
+            YammerError::ServiceConflict { name, sources }
+                if name == "redis" && *sources == [spec(0).spec, spec(1).spec]
+        ));
+        assert_eq!(error.services()["redis"]["image"], "redis:6");
+
+        assert_eq!(
+            "overwrite".parse::<ConflictPolicy>().unwrap(),
+            ConflictPolicy::Last
+        );
+        assert!("first".parse::<ConflictPolicy>().is_err());
+    }
+}
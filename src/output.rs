@@ -0,0 +1,642 @@
+use crate::{FileSource, YammerError, insert_service_comments};
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// The top-level key the generation metadata is written under.
+pub const ANNOTATION_KEY: &str = "x-yammer";
+
+/// Render a unified diff that turns the `existing` contents of `path` into `merged`.
+///
+/// A missing file should be passed as an empty string, in which case the patch adds every line.
+pub fn unified_patch(path: &str, existing: &str, merged: &str) -> String {
+    TextDiff::from_lines(existing, merged)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{path}"), &format!("b/{path}"))
+        .to_string()
+}
+
+/// A `key=value` pair given on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for KeyValue {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok(KeyValue {
+                key: key.trim().to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(YammerError::InvalidKeyValue(s.to_string())),
+        }
+    }
+}
+
+/// Replace every `{{key}}` placeholder in `contents` with its value.
+///
+/// Whitespace inside the braces is ignored, so `{{ key }}` works too. Placeholders without
+/// a value are left untouched, and a placeholder written as `\{{key}}` is kept as a literal
+/// `{{key}}`. This is unrelated to compose's own `${VAR}` interpolation.
+pub fn render_placeholders(contents: &str, values: &[KeyValue]) -> String {
+    let mut rendered = String::with_capacity(contents.len());
+    let mut rest = contents;
+    while let Some(start) = rest.find("{{") {
+        if let Some(escaped) = rest[..start].strip_suffix('\\') {
+            rendered.push_str(escaped);
+            rendered.push_str("{{");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+            break;
+        };
+        let key = rest[start + 2..end].trim();
+        rendered.push_str(&rest[..start]);
+        match values.iter().rev().find(|value| value.key == key) {
+            Some(value) => rendered.push_str(&value.value),
+            None => rendered.push_str(&rest[start..end + 2]),
+        }
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Fill the `{{key}}` placeholders in every string of `contents`, keys included, as
+/// [`render_placeholders`] does.
+///
+/// Placeholders are filled before the contents are serialized, so a value with quotes or
+/// backslashes in it is escaped like any other string in the output, whatever its format.
+pub fn fill_placeholders(contents: &mut serde_yaml::Mapping, values: &[KeyValue]) {
+    let filled = std::mem::take(contents)
+        .into_iter()
+        .map(|(mut key, mut value)| {
+            fill_value_placeholders(&mut key, values);
+            fill_value_placeholders(&mut value, values);
+            (key, value)
+        })
+        .collect();
+    *contents = filled;
+}
+
+fn fill_value_placeholders(value: &mut serde_yaml::Value, values: &[KeyValue]) {
+    match value {
+        serde_yaml::Value::String(string) => *string = render_placeholders(string, values),
+        serde_yaml::Value::Sequence(sequence) => sequence
+            .iter_mut()
+            .for_each(|value| fill_value_placeholders(value, values)),
+        serde_yaml::Value::Mapping(mapping) => fill_placeholders(mapping, values),
+        serde_yaml::Value::Tagged(tagged) => fill_value_placeholders(&mut tagged.value, values),
+        _ => {}
+    }
+}
+
+/// Metadata recording how an output file was generated: the version of this tool, when it
+/// ran and the specs it merged, to be written under [`ANNOTATION_KEY`].
+pub fn annotation(sources: &[String], generated_at: SystemTime) -> serde_yaml::Value {
+    let mut annotation = serde_yaml::Mapping::new();
+    annotation.insert("version".into(), env!("CARGO_PKG_VERSION").into());
+    annotation.insert(
+        "generated_at".into(),
+        humantime::format_rfc3339_seconds(generated_at)
+            .to_string()
+            .into(),
+    );
+    annotation.insert(
+        "sources".into(),
+        serde_yaml::Value::Sequence(
+            sources
+                .iter()
+                .map(|source| source.as_str().into())
+                .collect(),
+        ),
+    );
+    serde_yaml::Value::Mapping(annotation)
+}
+
+/// The format an output file is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Yaml,
+    Json,
+}
+
+impl OutputFormat {
+    /// The format a file's extension implies: JSON for `.json`, YAML for anything else.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("json") => OutputFormat::Json,
+            _ => OutputFormat::Yaml,
+        }
+    }
+
+    pub fn serialize(&self, contents: &impl serde::Serialize) -> Result<String, YammerError> {
+        match self {
+            OutputFormat::Yaml => Ok(serde_yaml::to_string(contents)?),
+            OutputFormat::Json => Ok(serde_json::to_string_pretty(contents)? + "\n"),
+        }
+    }
+}
+
+/// Unix permission bits for the output file, given in octal like `chmod` takes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMode(pub u32);
+
+impl FromStr for FileMode {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix("0o").unwrap_or(s);
+        // `from_str_radix` takes a leading sign too, which `chmod` doesn't.
+        let is_octal =
+            !digits.is_empty() && digits.bytes().all(|digit| matches!(digit, b'0'..=b'7'));
+        match u32::from_str_radix(digits, 8) {
+            Ok(mode) if is_octal && mode <= 0o7777 => Ok(FileMode(mode)),
+            _ => Err(YammerError::InvalidFileMode(s.to_string())),
+        }
+    }
+}
+
+/// Whether `path` already holds `contents` with the given permissions, so writing it again
+/// would change nothing.
+fn is_up_to_date(path: &Path, contents: &str, mode: Option<FileMode>) -> bool {
+    if std::fs::read(path).ok().as_deref() != Some(contents.as_bytes()) {
+        return false;
+    }
+    #[cfg(unix)]
+    if let Some(FileMode(mode)) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        return std::fs::metadata(path)
+            .is_ok_and(|metadata| metadata.permissions().mode() & 0o7777 == mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    true
+}
+
+/// Write the output file, with its permissions set to `mode` if one is given.
+///
+/// Without a mode, a new file gets the default permissions the process umask allows and an
+/// existing file keeps its own. Modes are only applied on Unix.
+///
+/// A file that's already up to date is left alone (keeping its mtime) unless `force` is set.
+/// Returns whether the file was written.
+pub fn write_output(
+    path: &Path,
+    contents: &str,
+    mode: Option<FileMode>,
+    force: bool,
+) -> Result<bool, YammerError> {
+    if !force && is_up_to_date(path, contents, mode) {
+        return Ok(false);
+    }
+    let io_error = |source| YammerError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if let Some(FileMode(mode)) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    let mut file = options.open(path).map_err(io_error)?;
+    // The mode only applies to a new file, and the umask may have narrowed it, so it's set
+    // again before anything is written to the (now empty) file.
+    #[cfg(unix)]
+    if let Some(FileMode(mode)) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(mode))
+            .map_err(io_error)?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    std::io::Write::write_all(&mut file, contents.as_bytes()).map_err(io_error)?;
+    Ok(true)
+}
+
+/// Serialize `contents` in the format `path`'s extension implies, with the `comments` of
+/// each service written above it when that's YAML.
+pub fn serialize_output(
+    path: &Path,
+    contents: &serde_yaml::Mapping,
+    comments: &BTreeMap<String, Vec<String>>,
+) -> Result<String, YammerError> {
+    let format = OutputFormat::from_path(path);
+    let serialized = format.serialize(contents)?;
+    match format {
+        OutputFormat::Yaml => Ok(insert_service_comments(&serialized, comments)),
+        OutputFormat::Json => Ok(serialized),
+    }
+}
+
+/// Write `contents` to each of `paths`, serialized by [`serialize_output`] and written by
+/// [`write_output`]. Returns whether each file was written.
+pub fn write_outputs(
+    paths: &[PathBuf],
+    contents: &serde_yaml::Mapping,
+    comments: &BTreeMap<String, Vec<String>>,
+    mode: Option<FileMode>,
+    force: bool,
+) -> Result<Vec<bool>, YammerError> {
+    paths
+        .iter()
+        .map(|path| {
+            write_output(
+                path,
+                &serialize_output(path, contents, comments)?,
+                mode,
+                force,
+            )
+        })
+        .collect()
+}
+
+/// Embed the merged top-level contents in a fixed scaffold read from an `--output-template`.
+///
+/// The template's services are kept, with merged services of the same name replacing them,
+/// and so are its other top-level sections unless the merged contents set them too.
+///
+/// The output is serialized anew, so the template's anchors don't survive: its aliases and
+/// `<<` merge keys are resolved to the values they refer to, see [`template_aliases`].
+pub fn apply_output_template(
+    template: &str,
+    contents: serde_yaml::Mapping,
+) -> Result<serde_yaml::Mapping, YammerError> {
+    let mut template: serde_yaml::Value = serde_yaml::from_str(template)?;
+    crate::materialize_merge_keys(&mut template)?;
+    let template: Option<serde_yaml::Mapping> = serde_yaml::from_value(template)?;
+    let mut templated = template.unwrap_or_default();
+    for (key, value) in contents {
+        match (templated.get_mut(&key), value) {
+            (
+                Some(serde_yaml::Value::Mapping(template_services)),
+                serde_yaml::Value::Mapping(services),
+            ) if key.as_str() == Some("services") => template_services.extend(services),
+            (_, value) => {
+                templated.insert(key, value);
+            }
+        }
+    }
+    Ok(templated)
+}
+
+/// The aliases an `--output-template` uses, which [`apply_output_template`] resolves rather
+/// than keeps, so they can be warned about.
+pub fn template_aliases(template: &str) -> Vec<String> {
+    crate::aliases_in(template).into_iter().collect()
+}
+
+/// The file a service is written to in split mode: `template` with `{project}`, `{repo}`,
+/// `{branch}` and `{service}` filled in from the service and the spec it came from.
+///
+/// For GitLab sources, `{project}` is the group the project is in and `{repo}` the project
+/// itself. Only Github and GitLab sources have these fields; for other sources, and for
+/// services that didn't come from any source, those fields are left empty.
+pub fn split_filename(template: &str, source: Option<&FileSource>, service: &str) -> PathBuf {
+    let (project, repo, branch) = match source {
+        Some(FileSource::Github(spec)) => (
+            spec.project.as_str(),
+            spec.repository.as_str(),
+            spec.branch.as_str(),
+        ),
+        Some(FileSource::Gitlab(spec)) => {
+            let (group, project) = spec.project.rsplit_once('/').unwrap_or(("", &spec.project));
+            (group, project, spec.branch.as_str())
+        }
+        _ => ("", "", ""),
+    };
+    PathBuf::from(
+        template
+            .replace("{project}", project)
+            .replace("{repo}", repo)
+            .replace("{branch}", branch)
+            .replace("{service}", service),
+    )
+}
+
+/// Split the merged top-level contents into one set per service, each with every other
+/// top-level section kept as it is but only that one service under `services`.
+pub fn split_by_service(contents: &serde_yaml::Mapping) -> Vec<(String, serde_yaml::Mapping)> {
+    let Some(serde_yaml::Value::Mapping(services)) = contents.get("services") else {
+        return vec![];
+    };
+    services
+        .iter()
+        .filter_map(|(name, service)| {
+            let mut split = contents.clone();
+            let mut single = serde_yaml::Mapping::new();
+            single.insert(name.clone(), service.clone());
+            split.insert("services".into(), serde_yaml::Value::Mapping(single));
+            Some((name.as_str()?.to_string(), split))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use crate::{MergeStrategy, merge_with_existing};
+
+    #[test]
+    fn test_unified_patch_shows_added_service() {
+        let existing = "services:\n  redis:\n    image: redis\n";
+        let mut composed = serde_yaml::Mapping::new();
+        let services: serde_yaml::Mapping = serde_yaml::from_str("mongo:\n  image: mongo").unwrap();
+        composed.insert("services".into(), serde_yaml::Value::Mapping(services));
+
+        let merged = merge_with_existing(Some(existing), composed, MergeStrategy::Replace).unwrap();
+        let merged = serde_yaml::to_string(&merged).unwrap();
+        let patch = unified_patch("docker-compose.yml", existing, &merged);
+
+        assert!(patch.contains("--- a/docker-compose.yml"));
+        assert!(patch.contains("+++ b/docker-compose.yml"));
+        assert!(patch.contains("+  mongo:\n"));
+        assert!(patch.contains("+    image: mongo\n"));
+        assert!(!patch.contains("-  redis:"));
+    }
+
+    #[test]
+    fn test_render_placeholders_in_image() {
+        let services: serde_yaml::Mapping =
+            serde_yaml::from_str("redis:\n  image: redis:{{tag}}\n").unwrap();
+        let serialized = serde_yaml::to_string(&services).unwrap();
+        let values = vec!["tag=7.2.4".parse().unwrap()];
+
+        let rendered = render_placeholders(&serialized, &values);
+
+        let rendered: serde_yaml::Mapping = serde_yaml::from_str(&rendered).unwrap();
+        assert_eq!(rendered["redis"]["image"], "redis:7.2.4");
+    }
+
+    #[test]
+    fn test_render_placeholders_leaves_unknown_and_unterminated() {
+        let values = vec![KeyValue {
+            key: "a".to_string(),
+            value: "1".to_string(),
+        }];
+        assert_eq!(
+            render_placeholders("{{ a }} {{b}} ${C} {{a", &values),
+            "1 {{b}} ${C} {{a"
+        );
+    }
+
+    #[test]
+    fn test_placeholders_can_be_escaped() {
+        let values = vec!["tag=7.2.4".parse().unwrap()];
+        assert_eq!(
+            render_placeholders(r"{{tag}} \{{tag}} \{{ other }}", &values),
+            "7.2.4 {{tag}} {{ other }}"
+        );
+    }
+
+    #[test]
+    fn test_filled_placeholders_are_escaped_by_the_serializer() {
+        let mut contents: serde_yaml::Mapping = serde_yaml::from_str(
+            "services:\n  app:\n    command: echo {{greeting}}\n    labels:\n      '{{key}}': x\n",
+        )
+        .unwrap();
+        let values = vec![
+            r#"greeting="hi" \ bye: #"#.parse().unwrap(),
+            "key=com.example".parse().unwrap(),
+        ];
+
+        fill_placeholders(&mut contents, &values);
+
+        let app = &contents["services"]["app"];
+        assert_eq!(app["command"], r#"echo "hi" \ bye: #"#);
+        assert_eq!(app["labels"]["com.example"], "x");
+        for format in [OutputFormat::Yaml, OutputFormat::Json] {
+            let serialized = format.serialize(&contents).unwrap();
+            let parsed: serde_yaml::Mapping = serde_yaml::from_str(&serialized).unwrap();
+            assert_eq!(parsed, contents);
+        }
+    }
+
+    #[test]
+    fn test_key_value_from_str() {
+        let pair: KeyValue = "tag=7=8".parse().unwrap();
+        assert_eq!(pair.key, "tag");
+        assert_eq!(pair.value, "7=8");
+        assert!("tag".parse::<KeyValue>().is_err());
+        assert!("=value".parse::<KeyValue>().is_err());
+    }
+
+    #[test]
+    fn test_unified_patch_no_changes_is_empty() {
+        let contents = "services:\n  redis:\n    image: redis\n";
+        assert!(unified_patch("docker-compose.yml", contents, contents).is_empty());
+    }
+
+    #[test]
+    fn test_annotation_fields() {
+        let generated_at = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let sources = vec![
+            "org/repo:docker-compose.yml@redis".to_string(),
+            "gist:abc123:docker-compose.yml@mongo".to_string(),
+        ];
+
+        let annotation = annotation(&sources, generated_at);
+
+        assert_eq!(annotation["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(annotation["generated_at"], "2023-11-14T22:13:20Z");
+        assert_eq!(
+            annotation["sources"][0],
+            "org/repo:docker-compose.yml@redis"
+        );
+        assert_eq!(
+            annotation["sources"][1],
+            "gist:abc123:docker-compose.yml@mongo"
+        );
+    }
+
+    #[test]
+    fn test_file_mode_from_str() {
+        assert_eq!("644".parse::<FileMode>().unwrap(), FileMode(0o644));
+        assert_eq!("0600".parse::<FileMode>().unwrap(), FileMode(0o600));
+        assert_eq!("0o640".parse::<FileMode>().unwrap(), FileMode(0o640));
+        assert!("999".parse::<FileMode>().is_err());
+        assert!("17777".parse::<FileMode>().is_err());
+        assert!("".parse::<FileMode>().is_err());
+        assert!("+644".parse::<FileMode>().is_err());
+        assert!("0o+644".parse::<FileMode>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_output_sets_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("chmod");
+        let path = dir.join("docker-compose.yml");
+        for mode in [0o600, 0o644, 0o600] {
+            write_output(&path, "services: {}\n", Some(FileMode(mode)), false).unwrap();
+            let permissions = std::fs::metadata(&path).unwrap().permissions();
+            assert_eq!(permissions.mode() & 0o7777, mode);
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "services: {}\n");
+    }
+
+    #[test]
+    fn test_write_output_skips_unchanged_file_unless_forced() {
+        let dir = TempDir::new("force");
+        let path = dir.join("docker-compose.yml");
+        let modified = || std::fs::metadata(&path).unwrap().modified().unwrap();
+        let backdate = || {
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH)
+                .unwrap()
+        };
+
+        assert!(write_output(&path, "services: {}\n", None, false).unwrap());
+        backdate();
+        assert!(!write_output(&path, "services: {}\n", None, false).unwrap());
+        assert_eq!(modified(), SystemTime::UNIX_EPOCH);
+
+        assert!(write_output(&path, "services: {}\n", None, true).unwrap());
+        assert!(modified() > SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_apply_output_template_keeps_scaffold() {
+        let template = r#"
+x-logging: &logging
+  driver: json-file
+networks:
+  backend:
+    driver: bridge
+services:
+  proxy:
+    image: traefik
+  redis:
+    image: redis:6
+"#;
+        let mut contents = serde_yaml::Mapping::new();
+        contents.insert(
+            "services".into(),
+            serde_yaml::from_str("redis:\n  image: redis:7\napi:\n  image: api\n").unwrap(),
+        );
+
+        let templated = apply_output_template(template, contents).unwrap();
+
+        assert_eq!(templated["networks"]["backend"]["driver"], "bridge");
+        assert_eq!(templated["x-logging"]["driver"], "json-file");
+        let services = &templated["services"];
+        assert_eq!(services["proxy"]["image"], "traefik");
+        assert_eq!(services["redis"]["image"], "redis:7");
+        assert_eq!(services["api"]["image"], "api");
+    }
+
+    #[test]
+    fn test_apply_output_template_resolves_aliases() {
+        let template = r#"
+x-logging: &logging
+  logging:
+    driver: json-file
+services:
+  proxy:
+    <<: *logging
+    image: traefik
+"#;
+        assert_eq!(template_aliases(template), ["logging"]);
+        assert!(template_aliases("services: {}").is_empty());
+
+        let templated = apply_output_template(template, serde_yaml::Mapping::new()).unwrap();
+        let proxy = templated["services"]["proxy"].as_mapping().unwrap();
+        assert_eq!(proxy["logging"]["driver"], "json-file");
+        assert!(!proxy.contains_key("<<"));
+    }
+
+    #[test]
+    fn test_split_filename_from_template() {
+        let source = |spec: &str| {
+            let spec: crate::ComposeServiceSpec = spec.parse().unwrap();
+            spec.source
+        };
+        let api = source("acme/backend+develop:docker-compose.yml@api");
+        let web = source("acme/frontend+main:compose.yaml@web");
+        let template = "{project}/{repo}-{branch}-{service}.yml";
+
+        assert_eq!(
+            split_filename(template, Some(&api), "api"),
+            PathBuf::from("acme/backend-develop-api.yml")
+        );
+        assert_eq!(
+            split_filename(template, Some(&web), "web"),
+            PathBuf::from("acme/frontend-main-web.yml")
+        );
+        assert_eq!(
+            split_filename("{repo}-{service}.yml", None, "db"),
+            PathBuf::from("-db.yml")
+        );
+    }
+
+    #[test]
+    fn test_split_by_service() {
+        let contents: serde_yaml::Mapping = serde_yaml::from_str(
+            "version: '3'\nservices:\n  api:\n    image: api\n  web:\n    image: web\n",
+        )
+        .unwrap();
+
+        let split = split_by_service(&contents);
+
+        let names: Vec<&str> = split.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["api", "web"]);
+        let (_, web) = &split[1];
+        assert_eq!(web["version"], "3");
+        let services = web["services"].as_mapping().unwrap();
+        assert_eq!(services.len(), 1);
+        assert_eq!(services["web"]["image"], "web");
+    }
+
+    #[test]
+    fn test_outputs_are_written_in_the_format_of_their_extension() {
+        let dir = TempDir::new("outputs");
+        let mut contents: serde_yaml::Mapping = serde_yaml::from_str(
+            "services:\n  redis:\n    image: redis\n    command: redis-server {{args}}\n",
+        )
+        .unwrap();
+        fill_placeholders(&mut contents, &[r#"args="a b" C:\dir"#.parse().unwrap()]);
+        let comments = BTreeMap::from([("redis".to_string(), vec!["# cache".to_string()])]);
+
+        let outputs = [
+            dir.join("docker-compose.yml"),
+            dir.join("docker-compose.json"),
+        ];
+        let written = write_outputs(&outputs, &contents, &comments, None, false).unwrap();
+        assert_eq!(written, [true, true]);
+
+        let yaml = std::fs::read_to_string(&outputs[0]).unwrap();
+        assert!(yaml.contains("  # cache\n  redis:\n"));
+        let yaml: serde_yaml::Mapping = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(yaml, contents);
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&outputs[1]).unwrap()).unwrap();
+        assert_eq!(json["services"]["redis"]["image"], "redis");
+        assert_eq!(
+            json["services"]["redis"]["command"],
+            r#"redis-server "a b" C:\dir"#
+        );
+        assert_eq!(
+            write_outputs(&outputs, &contents, &comments, None, false).unwrap(),
+            [false, false]
+        );
+        assert_eq!(
+            OutputFormat::from_path(Path::new("out.JSON")),
+            OutputFormat::Json
+        );
+    }
+}
@@ -0,0 +1,289 @@
+use crate::{ComposeServiceSpec, DockerComposeFile, YammerError, deep_merge_with};
+use serde_yaml::{Mapping, Value};
+
+/// Merge `later` into `earlier` the way docker merges a file given with a later `-f`:
+/// mappings key by key, and sequences by appending the entries `earlier` doesn't have yet.
+fn merge_value(earlier: Value, later: Value) -> Value {
+    deep_merge_with(earlier, later, &|earlier, later| {
+        merge_sequence_by(earlier, later, |_| None)
+    })
+}
+
+/// Append the entries of `later` to `earlier`, replacing any entry of `earlier` that has the
+/// same key and skipping exact duplicates.
+fn merge_sequence_by(
+    mut earlier: Vec<Value>,
+    later: Vec<Value>,
+    key: impl Fn(&Value) -> Option<String>,
+) -> Vec<Value> {
+    for entry in later {
+        let existing = match key(&entry) {
+            Some(entry_key) => earlier
+                .iter()
+                .position(|existing| key(existing).as_ref() == Some(&entry_key)),
+            None => earlier.iter().position(|existing| *existing == entry),
+        };
+        match existing {
+            Some(index) => earlier[index] = entry,
+            None => earlier.push(entry),
+        }
+    }
+    earlier
+}
+
+/// A `KEY=VALUE` list (like `environment` or `labels`) as a mapping; mappings are kept as is.
+fn key_value_mapping(value: Value) -> Value {
+    let Value::Sequence(entries) = value else {
+        return value;
+    };
+    let mapping: Mapping = entries
+        .iter()
+        .filter_map(Value::as_str)
+        .map(|entry| match entry.split_once('=') {
+            Some((key, value)) => (key.into(), value.into()),
+            None => (entry.into(), Value::Null),
+        })
+        .collect();
+    Value::Mapping(mapping)
+}
+
+/// The path a `volumes` or `devices` entry is mounted at inside the container.
+fn mount_target(entry: &Value) -> Option<String> {
+    match entry {
+        Value::String(entry) => {
+            let mut parts = entry.split(':');
+            let first = parts.next()?;
+            Some(parts.next().unwrap_or(first).to_string())
+        }
+        Value::Mapping(entry) => entry.get("target")?.as_str().map(str::to_string),
+        _ => None,
+    }
+}
+
+/// What compose tells `ports` entries apart by: the host IP, the published port, the
+/// container port and the protocol, in either the short or the long syntax.
+fn port_key(entry: &Value) -> Option<String> {
+    let scalar = |value: Option<&Value>| match value {
+        Some(Value::Number(value)) => value.to_string(),
+        Some(Value::String(value)) => value.clone(),
+        _ => String::new(),
+    };
+    let (host_ip, published, target, protocol) = match entry {
+        Value::String(entry) => {
+            let (ports, protocol) = entry.split_once('/').unwrap_or((entry, "tcp"));
+            // The host IP may be an IPv6 address, which has colons of its own.
+            let mut parts = ports.rsplitn(3, ':');
+            let target = parts.next()?;
+            let published = parts.next().unwrap_or_default();
+            let host_ip = parts.next().unwrap_or_default();
+            (
+                host_ip.to_string(),
+                published.to_string(),
+                target.to_string(),
+                protocol.to_string(),
+            )
+        }
+        Value::Number(target) => (
+            String::new(),
+            String::new(),
+            target.to_string(),
+            "tcp".to_string(),
+        ),
+        Value::Mapping(entry) => (
+            scalar(entry.get("host_ip")),
+            scalar(entry.get("published")),
+            scalar(Some(entry.get("target")?)),
+            entry
+                .get("protocol")
+                .and_then(Value::as_str)
+                .unwrap_or("tcp")
+                .to_string(),
+        ),
+        _ => return None,
+    };
+    Some(format!("{host_ip}|{published}|{target}/{protocol}"))
+}
+
+/// Apply an override to a single service, following docker's merge rules: `command` and
+/// `entrypoint` are replaced, `environment` and `labels` are merged by key, `volumes` and
+/// `devices` by their path in the container, `ports` by their host IP, published port,
+/// container port and protocol (so an override publishing a container port on another host
+/// port adds to the earlier one rather than remapping it) and everything else as in
+/// [`merge_value`].
+pub fn override_service(earlier: Value, later: Value) -> Value {
+    let (mut earlier, later) = match (earlier, later) {
+        (Value::Mapping(earlier), Value::Mapping(later)) => (earlier, later),
+        (_, later) => return later,
+    };
+    for (key, value) in later {
+        let Some(existing) = earlier.get_mut(&key) else {
+            earlier.insert(key, value);
+            continue;
+        };
+        let existing_value = std::mem::take(existing);
+        *existing = match (key.as_str(), existing_value, value) {
+            (Some("command" | "entrypoint"), _, value) => value,
+            (Some("environment" | "labels"), existing, value) => {
+                merge_value(key_value_mapping(existing), key_value_mapping(value))
+            }
+            (Some("volumes" | "devices"), Value::Sequence(existing), Value::Sequence(value)) => {
+                Value::Sequence(merge_sequence_by(existing, value, mount_target))
+            }
+            (Some("ports"), Value::Sequence(existing), Value::Sequence(value)) => {
+                Value::Sequence(merge_sequence_by(existing, value, port_key))
+            }
+            (_, existing, value) => merge_value(existing, value),
+        };
+    }
+    Value::Mapping(earlier)
+}
+
+impl DockerComposeFile {
+    /// Layer an override file on top of this one, as `docker compose -f this -f other` would.
+    pub fn apply_override(&mut self, other: DockerComposeFile) {
+        if other.version.is_some() {
+            self.version = other.version;
+        }
+        if let Some(other_services) = other.services {
+            let services = self.services.get_or_insert_with(Default::default);
+            for (name, service) in other_services {
+                match services.get_mut(&name) {
+                    Some(existing) => {
+                        *existing = override_service(std::mem::take(existing), service)
+                    }
+                    None => {
+                        services.insert(name, service);
+                    }
+                }
+            }
+        }
+        for (key, value) in other.other {
+            match self.other.get_mut(&key) {
+                Some(existing) => *existing = merge_value(std::mem::take(existing), value),
+                None => {
+                    self.other.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+/// Collapse the files downloaded for every spec (in the order of [`ComposeServiceSpec::files`])
+/// into one file per spec, with its overrides applied in order.
+///
+/// A spec fails as a whole if any of its files couldn't be downloaded or parsed.
+pub fn layer_overrides(
+    specs: &[ComposeServiceSpec],
+    files: Vec<Result<DockerComposeFile, YammerError>>,
+) -> Vec<Result<DockerComposeFile, YammerError>> {
+    let mut files = files.into_iter();
+    specs
+        .iter()
+        .map(|spec| {
+            // Take every layer up front, so a failing spec doesn't shift the files of the next.
+            let layers: Vec<_> = files.by_ref().take(1 + spec.overrides.len()).collect();
+            let mut layers = layers.into_iter();
+            let mut base = layers.next().ok_or(YammerError::NotYetDownloaded)??;
+            for layer in layers {
+                base.apply_override(layer?);
+            }
+            Ok(base)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer};
+    use crate::{DownloadFile, FileSource, GithubFileDownloader, SourceDownloader};
+
+    #[test]
+    fn test_override_service_follows_docker_rules() {
+        let base: Value = serde_yaml::from_str(
+            r#"
+            image: nginx
+            command: [nginx, -g, daemon off;]
+            ports: ["8080:80", "443:443"]
+            environment: [A=1, B=1]
+            volumes: ["./html:/usr/share/nginx/html", "logs:/var/log"]"#,
+        )
+        .unwrap();
+        let over: Value = serde_yaml::from_str(
+            r#"
+            command: [nginx-debug]
+            ports: ["9090:80", {target: 443, published: 443}]
+            environment:
+              B: "2"
+            volumes: ["./dist:/usr/share/nginx/html"]"#,
+        )
+        .unwrap();
+
+        let merged = override_service(base, over);
+
+        assert_eq!(merged["image"], "nginx");
+        assert_eq!(
+            merged["command"],
+            serde_yaml::from_str::<Value>("[nginx-debug]").unwrap()
+        );
+        assert_eq!(
+            merged["ports"],
+            serde_yaml::from_str::<Value>(
+                r#"["8080:80", {target: 443, published: 443}, "9090:80"]"#
+            )
+            .unwrap()
+        );
+        assert_eq!(merged["environment"]["A"], "1");
+        assert_eq!(merged["environment"]["B"], "2");
+        assert_eq!(merged["volumes"][0], "./dist:/usr/share/nginx/html");
+        assert_eq!(merged["volumes"][1], "logs:/var/log");
+    }
+
+    #[test]
+    fn test_port_key_follows_compose() {
+        let key = |entry: &str| port_key(&serde_yaml::from_str(entry).unwrap());
+        assert_eq!(key(r#""8080:80""#), key("{target: 80, published: 8080}"));
+        assert_ne!(key(r#""8080:80""#), key(r#""9090:80""#));
+        assert_ne!(key(r#""8080:80""#), key(r#""127.0.0.1:8080:80""#));
+        assert_ne!(key(r#""53:53""#), key(r#""53:53/udp""#));
+        assert_eq!(key(r#""[::1]:8080:80""#).unwrap(), "[::1]|8080|80/tcp");
+        assert_eq!(key("80"), key("{target: 80}"));
+    }
+
+    #[tokio::test]
+    async fn test_layer_overrides_adds_selected_service_port() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/org/repo/refs/heads/main/docker-compose.yml" => {
+                MockResponse::ok("services:\n  web:\n    image: nginx\n    ports: [\"8080:80\"]\n")
+            }
+            "/org/repo/refs/heads/main/docker-compose.override.yml" => {
+                MockResponse::ok("services:\n  web:\n    ports: [\"9090:80\"]\n")
+            }
+            _ => MockResponse::new(404, "not found"),
+        })
+        .await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let specs: Vec<ComposeServiceSpec> = vec![
+            "org/repo+main:docker-compose.yml,docker-compose.override.yml@web"
+                .parse()
+                .unwrap(),
+        ];
+
+        let sources: Vec<&FileSource> = specs.iter().flat_map(|spec| spec.files()).collect();
+        assert_eq!(sources.len(), 2);
+        let mut files = vec![];
+        for source in sources {
+            files.push(downloader.download_compose_file(source).await);
+        }
+        let layered = layer_overrides(&specs, files);
+
+        assert_eq!(layered.len(), 1);
+        let web = layered[0].as_ref().unwrap().get_service("web").unwrap();
+        assert_eq!(web["image"], "nginx");
+        assert_eq!(
+            web["ports"],
+            serde_yaml::from_str::<Value>(r#"["8080:80", "9090:80"]"#).unwrap()
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{DockerComposeFile, FileSource, YammerError};
+use std::time::Duration;
+
+/// Hooks into the downloads of [`crate::download_compose_files_with_progress`], e.g. to drive
+/// a progress bar. Downloads run concurrently, so the hooks of different sources interleave.
+pub trait DownloadProgress: Send + Sync {
+    /// The download of `source` is starting.
+    fn started(&self, _source: &FileSource) {}
+
+    /// The download of `source` took `elapsed` and ended with `result`.
+    fn finished(
+        &self,
+        _source: &FileSource,
+        _result: &Result<DockerComposeFile, YammerError>,
+        _elapsed: Duration,
+    ) {
+    }
+}
+
+/// Reports nothing.
+impl DownloadProgress for () {}
+
+/// Logs each download to stderr as it starts and ends, with how long it took.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogProgress;
+
+impl DownloadProgress for LogProgress {
+    fn started(&self, source: &FileSource) {
+        eprintln!("downloading {source}");
+    }
+
+    fn finished(
+        &self,
+        source: &FileSource,
+        result: &Result<DockerComposeFile, YammerError>,
+        elapsed: Duration,
+    ) {
+        match result {
+            Ok(_) => eprintln!("downloaded {source} in {elapsed:.2?}"),
+            Err(err) => eprintln!("failed to download {source} after {elapsed:.2?}: {err}"),
+        }
+    }
+}
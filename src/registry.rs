@@ -0,0 +1,228 @@
+use crate::{ComposeServiceSpec, DownloadFile, FileSource, YammerError};
+use crate::{parse_gist_spec, parse_gitlab_spec, parse_local_spec, parse_url_spec, parse_zip_spec};
+use crate::{parse_service_selections, parse_spec, split_services};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// A file from a source registered with [`SourceRegistry::with_scheme`]: everything in its DSN
+/// between the `scheme:` prefix and the `@services`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomFileSpec {
+    pub scheme: String,
+    pub path: String,
+}
+
+impl CustomFileSpec {
+    pub fn new(scheme: impl Into<String>, path: impl Into<String>) -> Self {
+        Self {
+            scheme: scheme.into(),
+            path: path.into(),
+        }
+    }
+}
+
+/// Downloads the files of a custom scheme.
+pub type CustomDownloader = Arc<dyn DownloadFile<FileSpec = CustomFileSpec> + Send + Sync>;
+
+/// How the DSNs of a scheme are parsed, and downloaded.
+#[derive(Clone)]
+enum Scheme {
+    /// A source built into [`crate::SourceDownloader`], parsed from what follows the prefix.
+    Builtin(fn(&str) -> Result<ComposeServiceSpec, YammerError>),
+    Custom(CustomDownloader),
+}
+
+/// Maps DSN scheme prefixes (like `gist:` in `gist:<id>:<filename>@<services>`) to the
+/// sources they stand for. DSNs without a registered prefix are Github DSNs.
+///
+/// `github:`, `gitlab:`, `gist:`, `zip:`, `file:`, `http:` and `https:` are built in, and
+/// library code can add its own with [`SourceRegistry::with_scheme`].
+#[derive(Clone)]
+pub struct SourceRegistry {
+    schemes: BTreeMap<String, Scheme>,
+}
+
+impl Default for SourceRegistry {
+    fn default() -> Self {
+        let builtins: [(&str, Scheme); 7] = [
+            ("github", Scheme::Builtin(|dsn| Ok(parse_spec(dsn)?.into()))),
+            ("gitlab", Scheme::Builtin(parse_gitlab_spec)),
+            ("gist", Scheme::Builtin(parse_gist_spec)),
+            ("zip", Scheme::Builtin(parse_zip_spec)),
+            ("file", Scheme::Builtin(parse_local_spec)),
+            (
+                "http",
+                Scheme::Builtin(|url| parse_url_spec(&format!("http:{url}"))),
+            ),
+            (
+                "https",
+                Scheme::Builtin(|url| parse_url_spec(&format!("https:{url}"))),
+            ),
+        ];
+        Self {
+            schemes: builtins
+                .into_iter()
+                .map(|(scheme, handler)| (scheme.to_string(), handler))
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Debug for SourceRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.schemes.keys()).finish()
+    }
+}
+
+impl SourceRegistry {
+    /// Route DSNs like `<scheme>:<path>@<services>` to `downloader`, replacing whatever the
+    /// scheme was registered to before.
+    pub fn with_scheme(
+        mut self,
+        scheme: impl Into<String>,
+        downloader: impl DownloadFile<FileSpec = CustomFileSpec> + Send + Sync + 'static,
+    ) -> Self {
+        self.schemes
+            .insert(scheme.into(), Scheme::Custom(Arc::new(downloader)));
+        self
+    }
+
+    /// The downloader registered for a custom scheme.
+    pub fn downloader(&self, scheme: &str) -> Result<&CustomDownloader, YammerError> {
+        match self.schemes.get(scheme) {
+            Some(Scheme::Custom(downloader)) => Ok(downloader),
+            _ => Err(YammerError::UnknownScheme(scheme.to_string())),
+        }
+    }
+
+    /// Parse a DSN of any registered scheme, along with the override files it lists.
+    pub fn parse(&self, dsn: &str) -> Result<ComposeServiceSpec, YammerError> {
+        let scheme = dsn
+            .split_once(':')
+            .and_then(|(scheme, rest)| Some((scheme, self.schemes.get(scheme)?, rest)));
+        let spec = match scheme {
+            Some((_, Scheme::Builtin(parse), rest)) => parse(rest)?,
+            Some((scheme, Scheme::Custom(_), rest)) => parse_custom_spec(scheme, rest)?,
+            None => parse_spec(dsn)?.into(),
+        };
+        let mut paths = spec.source.path().split(',');
+        // Github specs may leave out the path to fall back to a default compose file.
+        let is_github = matches!(spec.source, FileSource::Github(_));
+        let Some(path) = paths
+            .next()
+            .filter(|path| !path.is_empty() || is_github && spec.source.path().is_empty())
+        else {
+            return Err(YammerError::UnknownSpec(
+                "path is not specified".to_string(),
+            ));
+        };
+        let source = spec.source.with_path(path);
+        let overrides = paths
+            .filter(|path| !path.is_empty())
+            .map(|path| source.sibling(path))
+            .collect();
+        Ok(ComposeServiceSpec {
+            source,
+            overrides,
+            services: spec.services,
+        })
+    }
+}
+
+fn parse_custom_spec(scheme: &str, rest: &str) -> Result<ComposeServiceSpec, YammerError> {
+    let (path, services_csv) = split_services(rest);
+    if path.is_empty() {
+        return Err(YammerError::UnknownSpec(format!(
+            "expected {scheme}:<path>@<services>"
+        )));
+    }
+    Ok(ComposeServiceSpec {
+        source: FileSource::Custom(CustomFileSpec::new(scheme, path)),
+        overrides: vec![],
+        services: parse_service_selections(services_csv)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FieldPatch, SourceDownloader, source_names};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use std::collections::HashMap;
+
+    /// Serves files from memory, keyed by path.
+    struct InMemory(HashMap<String, &'static str>);
+
+    #[async_trait]
+    impl DownloadFile for InMemory {
+        type FileSpec = CustomFileSpec;
+
+        async fn download_file(&self, spec: &Self::FileSpec) -> Result<Bytes, YammerError> {
+            self.0
+                .get(&spec.path)
+                .map(|contents| Bytes::from_static(contents.as_bytes()))
+                .ok_or_else(|| YammerError::UnknownSpec(spec.path.clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_scheme_routes_to_registered_downloader() {
+        let files = InMemory(HashMap::from([(
+            "stacks/cache.yml".to_string(),
+            "services:\n  redis:\n    image: redis\n",
+        )]));
+        let downloader = SourceDownloader::default()
+            .with_registry(SourceRegistry::default().with_scheme("mem", files));
+
+        let spec = downloader
+            .registry
+            .parse("mem:stacks/cache.yml@redis")
+            .unwrap();
+        assert!(
+            matches!(&spec.source, FileSource::Custom(file) if file.scheme == "mem" && file.path == "stacks/cache.yml")
+        );
+        assert_eq!(source_names(&spec.services), ["redis"]);
+        assert_eq!(spec.to_string(), "mem:stacks/cache.yml@redis");
+        let patched = downloader
+            .registry
+            .parse("mem:stacks/cache.yml@redis{image=redis:7@sha256:abc}")
+            .unwrap();
+        assert!(
+            matches!(&patched.source, FileSource::Custom(file) if file.path == "stacks/cache.yml")
+        );
+        assert_eq!(
+            patched.services[0].patches,
+            [FieldPatch::set("image", "redis:7@sha256:abc")]
+        );
+
+        let compose_file = downloader
+            .download_compose_file(&spec.source)
+            .await
+            .unwrap();
+        assert_eq!(compose_file.get_service("redis").unwrap()["image"], "redis");
+
+        let unregistered = SourceRegistry::default()
+            .with_scheme("other", InMemory(HashMap::new()))
+            .parse("other:cache.yml@redis")
+            .unwrap();
+        let err = downloader
+            .download_compose_file(&unregistered.source)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, YammerError::UnknownScheme(scheme) if scheme == "other"));
+    }
+
+    #[test]
+    fn test_builtin_schemes() {
+        let registry = SourceRegistry::default();
+        let github = registry.parse("github:org/repo:compose.yml@web").unwrap();
+        assert!(matches!(github.source, FileSource::Github(_)));
+        assert_eq!(github.to_string(), "org/repo:compose.yml@web");
+        assert!(matches!(
+            registry.parse("gist:abc:compose.yml@web").unwrap().source,
+            FileSource::Gist(_)
+        ));
+        assert!(registry.parse("mem:compose.yml@web").is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{YammerError, validate_service_name};
+use regex::Regex;
+use serde_yaml::Value;
+use std::str::FromStr;
+
+/// A sed-like `s/pattern/replacement/[g]` substitution applied to service names.
+///
+/// The replacement may refer to capture groups as `\1` (sed style) or `$1` / `${name}`
+/// (regex style). Any character can be used as the delimiter, e.g. `s|^x-||`, and a
+/// delimiter can be escaped with a backslash inside the pattern or replacement.
+#[derive(Debug, Clone)]
+pub struct RenamePattern {
+    pub regex: Regex,
+    pub replacement: String,
+    pub global: bool,
+}
+
+impl RenamePattern {
+    pub fn apply(&self, name: &str) -> String {
+        if self.global {
+            self.regex.replace_all(name, &self.replacement).into_owned()
+        } else {
+            self.regex.replace(name, &self.replacement).into_owned()
+        }
+    }
+}
+
+impl FromStr for RenamePattern {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| YammerError::InvalidRenamePattern(format!("{s}: {reason}"));
+
+        let mut chars = s.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid("expected it to start with `s`"));
+        }
+        let Some(delimiter) = chars.next() else {
+            return Err(invalid("missing delimiter"));
+        };
+
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for c in chars {
+            let part = parts.last_mut().unwrap();
+            if escaped {
+                if c != delimiter {
+                    part.push('\\');
+                }
+                part.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == delimiter {
+                parts.push(String::new());
+            } else {
+                part.push(c);
+            }
+        }
+        let [pattern, replacement, flags] = <[String; 3]>::try_from(parts)
+            .map_err(|_| invalid("expected s/pattern/replacement/[flags]"))?;
+
+        let global = match flags.as_str() {
+            "" => false,
+            "g" => true,
+            _ => return Err(invalid("the only supported flag is `g`")),
+        };
+        let regex = Regex::new(&pattern).map_err(|err| invalid(&err.to_string()))?;
+        Ok(Self {
+            regex,
+            replacement: sed_to_regex_replacement(&replacement),
+            global,
+        })
+    }
+}
+
+/// Turn sed-style `\1` group references into the `${1}` form the regex crate expects.
+fn sed_to_regex_replacement(replacement: &str) -> String {
+    let mut converted = String::new();
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(digit)) if digit.is_ascii_digit() => {
+                converted.push_str(&format!("${{{digit}}}"));
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                converted.push('\\');
+                chars.next();
+            }
+            _ => converted.push(c),
+        }
+    }
+    converted
+}
+
+/// Rename every service in a `services` mapping, rewriting `depends_on` and `links`
+/// references so they keep pointing at the renamed services.
+///
+/// Fails if a new name isn't a valid service name, or if two services would get the same
+/// name, rather than silently dropping one of them.
+pub fn rename_services(
+    services: &serde_yaml::Mapping,
+    rename: impl Fn(&str) -> String,
+) -> Result<serde_yaml::Mapping, YammerError> {
+    let mut renamed = serde_yaml::Mapping::new();
+    let mut renamed_from: Vec<(String, &str)> = vec![];
+    for (name, service) in services {
+        let name = match name.as_str() {
+            Some(original) => {
+                let name = rename(original);
+                validate_service_name(&name, false)?;
+                if let Some((_, first)) = renamed_from.iter().find(|(seen, _)| *seen == name) {
+                    return Err(YammerError::InvalidRenamePattern(format!(
+                        "both `{first}` and `{original}` would be renamed to `{name}`"
+                    )));
+                }
+                renamed_from.push((name.clone(), original));
+                Value::String(name)
+            }
+            None => name.clone(),
+        };
+        let mut service = service.clone();
+        if let Some(service) = service.as_mapping_mut() {
+            rename_references(service, &rename);
+        }
+        renamed.insert(name, service);
+    }
+    Ok(renamed)
+}
+
+/// Rewrite the `depends_on` and `links` references of a single service with `rename`.
+pub fn rename_references(service: &mut serde_yaml::Mapping, rename: &impl Fn(&str) -> String) {
+    match service.get_mut("depends_on") {
+        Some(Value::Sequence(dependencies)) => {
+            for dependency in dependencies {
+                if let Some(name) = dependency.as_str() {
+                    *dependency = Value::String(rename(name));
+                }
+            }
+        }
+        Some(Value::Mapping(dependencies)) => {
+            *dependencies = std::mem::take(dependencies)
+                .into_iter()
+                .map(|(name, condition)| match name.as_str() {
+                    Some(name) => (Value::String(rename(name)), condition),
+                    None => (name, condition),
+                })
+                .collect();
+        }
+        _ => {}
+    }
+
+    if let Some(Value::Sequence(links)) = service.get_mut("links") {
+        for link in links {
+            let Some(value) = link.as_str() else {
+                continue;
+            };
+            *link = Value::String(match value.split_once(':') {
+                Some((name, alias)) => format!("{}:{alias}", rename(name)),
+                None => rename(value),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_pattern_from_str() {
+        let pattern: RenamePattern = "s/^x-//".parse().unwrap();
+        assert_eq!(pattern.apply("x-postgres"), "postgres");
+        assert_eq!(pattern.apply("redis"), "redis");
+
+        let pattern: RenamePattern = r"s|^(\w+)-(\d+)$|\2-\1|".parse().unwrap();
+        assert_eq!(pattern.apply("worker-1"), "1-worker");
+
+        let pattern: RenamePattern = r"s/\//_/g".parse().unwrap();
+        assert_eq!(pattern.apply("a/b/c"), "a_b_c");
+
+        let pattern: RenamePattern = "s/-/_/".parse().unwrap();
+        assert_eq!(pattern.apply("a-b-c"), "a_b-c");
+    }
+
+    #[test]
+    fn test_rename_pattern_from_str_invalid() {
+        assert!("y/a/b/".parse::<RenamePattern>().is_err());
+        assert!("s/a/b".parse::<RenamePattern>().is_err());
+        assert!("s/a/b/x".parse::<RenamePattern>().is_err());
+        assert!("s/(/b/".parse::<RenamePattern>().is_err());
+    }
+
+    #[test]
+    fn test_rename_services_strips_prefix_and_updates_references() {
+        let services: serde_yaml::Mapping = serde_yaml::from_str(
+            r#"
+            x-postgres:
+              image: postgres
+            x-redis:
+              image: redis
+            api:
+              image: api
+              depends_on:
+                - x-postgres
+              links:
+                - x-redis:cache
+            worker:
+              image: worker
+              depends_on:
+                x-redis:
+                  condition: service_healthy"#,
+        )
+        .unwrap();
+        let pattern: RenamePattern = "s/^x-//".parse().unwrap();
+
+        let renamed = rename_services(&services, |name| pattern.apply(name)).unwrap();
+
+        let names: Vec<_> = renamed.keys().filter_map(Value::as_str).collect();
+        assert_eq!(names, vec!["postgres", "redis", "api", "worker"]);
+        assert_eq!(
+            renamed["api"]["depends_on"],
+            serde_yaml::from_str::<Value>("[postgres]").unwrap()
+        );
+        assert_eq!(renamed["api"]["links"][0], "redis:cache");
+        assert!(
+            renamed["worker"]["depends_on"]
+                .as_mapping()
+                .unwrap()
+                .contains_key("redis")
+        );
+    }
+
+    #[test]
+    fn test_rename_services_rejects_collisions_and_invalid_names() {
+        let services: serde_yaml::Mapping = serde_yaml::from_str(
+            "worker-1:\n  image: worker\nworker-2:\n  image: worker\napi:\n  image: api\n",
+        )
+        .unwrap();
+
+        let pattern: RenamePattern = "s/-[0-9]+$//".parse().unwrap();
+        let err = rename_services(&services, |name| pattern.apply(name)).unwrap_err();
+        assert!(
+            matches!(&err, YammerError::InvalidRenamePattern(reason) if reason.contains("`worker-1` and `worker-2`")),
+            "{err:?}"
+        );
+
+        let pattern: RenamePattern = "s/^/my app /".parse().unwrap();
+        let err = rename_services(&services, |name| pattern.apply(name)).unwrap_err();
+        assert!(matches!(err, YammerError::InvalidServiceName(_)), "{err:?}");
+    }
+}
@@ -0,0 +1,454 @@
+use crate::{
+    ComposeServiceSpec, ComposeVersion, ConflictPolicy, DEFAULT_MAX_CONCURRENCY, DockerComposeFile,
+    DownloadFile, EnvConflictPolicy, FileMerger, FileSource, MergeStrategy, ServiceMerger, SpecId,
+    YammerError, layer_overrides, merge_with_existing,
+};
+use futures::{StreamExt, stream};
+use serde_yaml::Mapping;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+type FileTransform = Arc<dyn Fn(DockerComposeFile) -> DockerComposeFile + Send + Sync>;
+
+/// Download a file and run it through every transform, in order.
+async fn download_transformed<D>(
+    downloader: &D,
+    transforms: &[FileTransform],
+    source: &FileSource,
+) -> Result<DockerComposeFile, YammerError>
+where
+    D: DownloadFile<FileSpec = FileSource> + Sync + ?Sized,
+{
+    let compose_file = downloader.download_compose_file(source).await?;
+    Ok(transforms
+        .iter()
+        .fold(compose_file, |compose_file, transform| {
+            transform(compose_file)
+        }))
+}
+
+/// Downloads specs and merges the services they select.
+///
+/// The CLI merges with the [`FileMerger`] from [`ComposeMerger::file_merger`] and lays the
+/// result over its output with [`ComposeMerger::lay_over_existing`], the same steps
+/// [`ComposeMerger::render`] takes, so the two merge alike.
+pub struct ComposeMerger<'a, D: ?Sized> {
+    downloader: &'a D,
+    strategy: MergeStrategy,
+    env_conflict: EnvConflictPolicy,
+    on_conflict: ConflictPolicy,
+    file_transforms: Vec<FileTransform>,
+    max_concurrency: usize,
+    compose_version: ComposeVersion,
+    with_dependencies: bool,
+    with_dependents: bool,
+    source_order: bool,
+    hoist_anchors: bool,
+}
+
+impl<'a, D> ComposeMerger<'a, D>
+where
+    D: DownloadFile<FileSpec = FileSource> + Sync + ?Sized,
+{
+    pub fn new(downloader: &'a D) -> Self {
+        Self {
+            downloader,
+            strategy: MergeStrategy::default(),
+            env_conflict: EnvConflictPolicy::default(),
+            on_conflict: ConflictPolicy::default(),
+            file_transforms: vec![],
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+            compose_version: ComposeVersion::default(),
+            with_dependencies: false,
+            with_dependents: false,
+            source_order: false,
+            hoist_anchors: false,
+        }
+    }
+
+    /// Download at most this many files at once in [`ComposeMerger::render`].
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency;
+        self
+    }
+
+    /// The `version` to give the output, instead of the first one the sources declare.
+    pub fn with_compose_version(mut self, compose_version: ComposeVersion) -> Self {
+        self.compose_version = compose_version;
+        self
+    }
+
+    pub fn with_merge_strategy(mut self, strategy: MergeStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn with_env_conflict(mut self, env_conflict: EnvConflictPolicy) -> Self {
+        self.env_conflict = env_conflict;
+        self
+    }
+
+    /// What to do when more than one spec selects a service of the same name.
+    ///
+    /// [`ComposeMerger::render`] doesn't look up when sources were last modified, so it fails
+    /// with [`ConflictPolicy::Newest`]. Callers that look them up can pass them to the
+    /// [`FileMerger`] from [`ComposeMerger::file_merger`].
+    pub fn with_on_conflict(mut self, on_conflict: ConflictPolicy) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    /// Also select the services the selected ones depend on, see
+    /// [`FileMerger::with_dependencies`].
+    pub fn with_dependencies(mut self, with_dependencies: bool) -> Self {
+        self.with_dependencies = with_dependencies;
+        self
+    }
+
+    /// Also select the services that depend on the selected ones, see
+    /// [`FileMerger::with_dependents`].
+    pub fn with_dependents(mut self, with_dependents: bool) -> Self {
+        self.with_dependents = with_dependents;
+        self
+    }
+
+    /// Select services in the order their files define them, see
+    /// [`FileMerger::with_source_order`].
+    pub fn with_source_order(mut self, source_order: bool) -> Self {
+        self.source_order = source_order;
+        self
+    }
+
+    /// Carry the anchored `x-` blocks the selected services use over to the top level, see
+    /// [`FileMerger::with_hoisted_anchors`].
+    pub fn with_hoisted_anchors(mut self, hoist_anchors: bool) -> Self {
+        self.hoist_anchors = hoist_anchors;
+        self
+    }
+
+    /// A [`FileMerger`] set up like this merger, for merging files one service at a time.
+    pub fn file_merger(&self) -> FileMerger {
+        FileMerger::new(
+            ServiceMerger::new(self.strategy, self.env_conflict).with_on_conflict(self.on_conflict),
+        )
+        .with_compose_version(self.compose_version.clone())
+        .with_dependencies(self.with_dependencies)
+        .with_dependents(self.with_dependents)
+        .with_source_order(self.source_order)
+        .with_hoisted_anchors(self.hoist_anchors)
+    }
+
+    /// Lay the `merged` contents over `existing`, the contents of a previous output file if
+    /// there is one, with this merger's strategy. See [`merge_with_existing`].
+    pub fn lay_over_existing(
+        &self,
+        existing: Option<&str>,
+        merged: Mapping,
+    ) -> Result<Mapping, YammerError> {
+        merge_with_existing(existing, merged, self.strategy)
+    }
+
+    /// Preprocess every downloaded file (overrides included) before services are selected
+    /// from it. Transforms run in the order they were added.
+    pub fn with_file_transform(
+        mut self,
+        transform: impl Fn(DockerComposeFile) -> DockerComposeFile + Send + Sync + 'static,
+    ) -> Self {
+        self.file_transforms.push(Arc::new(transform));
+        self
+    }
+
+    async fn download(&self, source: &FileSource) -> Result<DockerComposeFile, YammerError> {
+        download_transformed(self.downloader, &self.file_transforms, source).await
+    }
+
+    /// Download every spec, merge the services each one selects and lay the result over
+    /// `existing` (the contents of a previous output file, if there is one), returning the
+    /// merged YAML without touching the disk.
+    ///
+    /// Files are merged by [`ComposeMerger::file_merger`] and laid over `existing` by
+    /// [`ComposeMerger::lay_over_existing`], as the CLI does. The first `version` seen is kept,
+    /// unless [`ComposeMerger::with_compose_version`] says otherwise. Unlike the CLI, which
+    /// skips sources it can't download and services they don't define, this fails on the first
+    /// one.
+    ///
+    /// Fails with [`YammerError::NewestUnsupported`] if the conflict policy is
+    /// [`ConflictPolicy::Newest`], since it doesn't look up when sources were last modified.
+    pub async fn render(
+        &self,
+        specs: &[ComposeServiceSpec],
+        existing: Option<&str>,
+    ) -> Result<String, YammerError> {
+        if self.on_conflict == ConflictPolicy::Newest {
+            return Err(YammerError::NewestUnsupported);
+        }
+        let mut merger = self.file_merger().with_required_services(true);
+
+        let compose_files: Vec<_> = stream::iter(specs.iter().flat_map(|spec| spec.files()))
+            .map(|source| self.download(source))
+            .buffered(self.max_concurrency.max(1))
+            .collect()
+            .await;
+        for (index, (spec, compose_file)) in specs
+            .iter()
+            .zip(layer_overrides(specs, compose_files))
+            .enumerate()
+        {
+            let (services, _) = merger.select(spec, &compose_file?)?;
+            for service in services {
+                merger.add(&spec.id(index), service, None)?;
+            }
+        }
+        let composed = merger.finish().contents;
+        let all_contents = self.lay_over_existing(existing, composed)?;
+        Ok(serde_yaml::to_string(&all_contents)?)
+    }
+}
+
+impl<D> ComposeMerger<'_, D>
+where
+    D: DownloadFile<FileSpec = FileSource> + Clone + Send + Sync + 'static,
+{
+    /// Download every spec concurrently, sending each one's file (with its overrides layered
+    /// on and the file transforms applied) over the returned channel as soon as it's ready.
+    ///
+    /// A spec that fails to download is sent as an error without affecting the others. The
+    /// channel closes once every spec has been sent. Must be called within a tokio runtime.
+    pub fn stream_results(
+        &self,
+        specs: &[ComposeServiceSpec],
+    ) -> mpsc::Receiver<(SpecId, Result<DockerComposeFile, YammerError>)> {
+        let (sender, receiver) = mpsc::channel(specs.len().max(1));
+        for (index, spec) in specs.iter().enumerate() {
+            let (sender, spec) = (sender.clone(), spec.clone());
+            let downloader = self.downloader.clone();
+            let transforms = self.file_transforms.clone();
+            tokio::spawn(async move {
+                let mut files = vec![];
+                for source in spec.files() {
+                    files.push(download_transformed(&downloader, &transforms, source).await);
+                }
+                let layered = layer_overrides(std::slice::from_ref(&spec), files)
+                    .pop()
+                    .unwrap_or(Err(YammerError::NotYetDownloaded));
+                // The caller may have stopped listening, which is fine.
+                let _ = sender.send((spec.id(index), layered)).await;
+            });
+        }
+        receiver
+    }
+}
+
+/// Render specs with the default [`ComposeMerger`]. See [`ComposeMerger::render`].
+pub async fn render_merged<D>(
+    specs: &[ComposeServiceSpec],
+    downloader: &D,
+    existing: Option<&str>,
+) -> Result<String, YammerError>
+where
+    D: DownloadFile<FileSpec = FileSource> + Sync + ?Sized,
+{
+    ComposeMerger::new(downloader).render(specs, existing).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer};
+    use crate::{DockerComposeFile, GithubFileDownloader, SourceDownloader};
+
+    #[tokio::test]
+    async fn test_render_merged_returns_merged_yaml() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/org/repo/refs/heads/main/docker-compose.yml" => MockResponse::ok(
+                "version: '3'\nservices:\n  redis:\n    image: redis\n  mongo:\n    image: mongo\n",
+            ),
+            _ => MockResponse::new(404, "not found"),
+        })
+        .await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let specs = vec!["org/repo+main:docker-compose.yml@redis".parse().unwrap()];
+        let existing = "services:\n  api:\n    image: api\n";
+
+        let rendered = render_merged(&specs, &downloader, Some(existing))
+            .await
+            .unwrap();
+
+        let compose_file: DockerComposeFile = serde_yaml::from_str(&rendered).unwrap();
+        assert_eq!(compose_file.version.as_deref(), Some("3"));
+        assert_eq!(compose_file.get_service("redis").unwrap()["image"], "redis");
+        assert!(compose_file.get_service("api").is_some());
+        assert!(compose_file.get_service("mongo").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_render_merged_fails_on_download_error() {
+        let server = MockServer::start(|_| MockResponse::new(404, "not found")).await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let specs = vec!["org/repo+main:docker-compose.yml@redis".parse().unwrap()];
+
+        assert!(render_merged(&specs, &downloader, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_render_merged_rejects_files_that_are_not_compose_files() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/org/repo/refs/heads/main/docker-compose.yml" => {
+                MockResponse::ok("services:\n  redis:\n    image: redis\n")
+            }
+            _ => MockResponse::ok("name: ci\non: [push]\njobs: {}\n"),
+        })
+        .await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+
+        let specs = vec![
+            "org/repo+main:.github/workflows/ci.yml@redis"
+                .parse()
+                .unwrap(),
+        ];
+        let err = render_merged(&specs, &downloader, None).await.unwrap_err();
+        assert!(matches!(err, YammerError::NotAComposeFile), "{err}");
+
+        let specs = vec![
+            "org/repo+main:docker-compose.yml@redis,mongo"
+                .parse()
+                .unwrap(),
+        ];
+        let err = render_merged(&specs, &downloader, None).await.unwrap_err();
+        assert!(
+            matches!(&err, YammerError::ServiceNotFound { service, file } if service == "mongo" && file.contains("docker-compose.yml")),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_render_selects_like_the_cli() {
+        let server = MockServer::start(|_| {
+            MockResponse::ok(
+                "services:\n  worker-1:\n    image: worker\n    networks: [jobs]\n  worker-2:\n    image: worker\n  api:\n    image: api\nnetworks:\n  jobs: {}\n",
+            )
+        })
+        .await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let specs = vec!["org/repo+main:docker-compose.yml@worker-*".parse().unwrap()];
+
+        let rendered = render_merged(&specs, &downloader, None).await.unwrap();
+
+        let compose_file: DockerComposeFile = serde_yaml::from_str(&rendered).unwrap();
+        assert_eq!(
+            compose_file.service_names().collect::<Vec<_>>(),
+            ["worker-1", "worker-2"]
+        );
+        assert!(compose_file.other["networks"]["jobs"].is_mapping());
+    }
+
+    #[tokio::test]
+    async fn test_render_merges_selected_extensions_at_the_top_level() {
+        let server = MockServer::start(|_| {
+            MockResponse::ok(
+                "x-common-env: &env\n  TZ: UTC\nservices:\n  api:\n    image: api\n    environment: *env\n",
+            )
+        })
+        .await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let specs = vec![
+            "org/repo+main:docker-compose.yml@api,x-common-env"
+                .parse()
+                .unwrap(),
+        ];
+
+        let rendered = render_merged(&specs, &downloader, None).await.unwrap();
+
+        let compose_file: DockerComposeFile = serde_yaml::from_str(&rendered).unwrap();
+        assert_eq!(compose_file.other["x-common-env"]["TZ"], "UTC");
+        assert_eq!(compose_file.service_names().collect::<Vec<_>>(), ["api"]);
+    }
+
+    #[tokio::test]
+    async fn test_file_transform_runs_before_selection() {
+        let server =
+            MockServer::start(|_| MockResponse::ok("services:\n  cache:\n    image: redis\n"))
+                .await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let specs = vec!["org/repo+main:docker-compose.yml@redis".parse().unwrap()];
+        let rename_cache = |mut compose_file: DockerComposeFile| {
+            if let Some(services) = &mut compose_file.services
+                && let Some(cache) = services.remove("cache")
+            {
+                services.insert("redis".into(), cache);
+            }
+            compose_file
+        };
+
+        let untransformed = render_merged(&specs, &downloader, None).await;
+        let rendered = ComposeMerger::new(&downloader)
+            .with_file_transform(rename_cache)
+            .render(&specs, None)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            untransformed,
+            Err(YammerError::ServiceNotFound { .. })
+        ));
+        let compose_file: DockerComposeFile = serde_yaml::from_str(&rendered).unwrap();
+        assert_eq!(compose_file.get_service("redis").unwrap()["image"], "redis");
+    }
+
+    #[tokio::test]
+    async fn test_render_rejects_the_newest_conflict_policy() {
+        let downloader = SourceDownloader::default();
+        let specs = vec!["org/repo+main:docker-compose.yml@redis".parse().unwrap()];
+
+        let result = ComposeMerger::new(&downloader)
+            .with_on_conflict(ConflictPolicy::Newest)
+            .render(&specs, None)
+            .await;
+
+        assert!(
+            matches!(result, Err(YammerError::NewestUnsupported)),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_results_delivers_each_source() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/org/repo/refs/heads/main/docker-compose.yml" => {
+                MockResponse::ok("services:\n  redis:\n    image: redis\n")
+            }
+            _ => MockResponse::new(404, "not found"),
+        })
+        .await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let specs: Vec<ComposeServiceSpec> = vec![
+            "org/repo+main:docker-compose.yml@redis".parse().unwrap(),
+            "org/repo+main:missing.yml@redis".parse().unwrap(),
+        ];
+
+        let mut results = ComposeMerger::new(&downloader).stream_results(&specs);
+        let mut received = vec![];
+        while let Some(result) = results.recv().await {
+            received.push(result);
+        }
+        received.sort_by_key(|(id, _)| id.index);
+
+        assert_eq!(received.len(), 2);
+        let (id, found) = &received[0];
+        assert_eq!(id.spec, "org/repo+main:docker-compose.yml@redis");
+        assert!(found.as_ref().unwrap().get_service("redis").is_some());
+        let (id, missing) = &received[1];
+        assert_eq!(id.index, 1);
+        assert_eq!(
+            missing.as_ref().unwrap_err().http_status(),
+            Some(reqwest::StatusCode::NOT_FOUND)
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// How often, and how patiently, a failed download is retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    /// The longest a server's `Retry-After` is honoured for. A response asking for a longer
+    /// wait fails right away instead of hanging the download.
+    pub max_wait: Duration,
+}
+
+/// How long a `Retry-After` is honoured for by default.
+pub const DEFAULT_MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, base_delay: Duration) -> Self {
+        Self {
+            max_retries,
+            base_delay,
+            max_wait: DEFAULT_MAX_RETRY_WAIT,
+        }
+    }
+
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self::new(0, Duration::ZERO)
+    }
+
+    /// The exponential backoff to wait before the given (zero-based) retry.
+    pub fn delay_for(&self, retry: u32) -> Duration {
+        self.base_delay.saturating_mul(2u32.saturating_pow(retry))
+    }
+
+    /// Caps how long a server's `Retry-After` is honoured for.
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3, Duration::from_millis(250))
+    }
+}
+
+/// How long a response asks to wait before trying again, from its `Retry-After` header in
+/// either of its forms: a number of seconds, or the HTTP date to wait until.
+pub fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let until = httpdate::parse_http_date(value).ok()?;
+    Some(
+        until
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Decides which HTTP statuses count as transient and are worth retrying.
+///
+/// By default that's any server error and `429 Too Many Requests`.
+#[derive(Clone)]
+pub struct RetryPredicate(Arc<dyn Fn(&reqwest::StatusCode) -> bool + Send + Sync>);
+
+impl RetryPredicate {
+    pub fn new(predicate: impl Fn(&reqwest::StatusCode) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(predicate))
+    }
+
+    pub fn should_retry(&self, status: &reqwest::StatusCode) -> bool {
+        (self.0)(status)
+    }
+}
+
+impl Default for RetryPredicate {
+    fn default() -> Self {
+        Self::new(|status| status.is_server_error() || status.as_u16() == 429)
+    }
+}
+
+impl std::fmt::Debug for RetryPredicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RetryPredicate(..)")
+    }
+}
+
+/// A total number of retries shared by every download in a run.
+///
+/// Clones share the same budget, so a single budget can be handed to many downloaders.
+/// Once exhausted, transient failures are reported immediately instead of being retried.
+#[derive(Debug, Clone)]
+pub struct RetryBudget {
+    remaining: Arc<AtomicU32>,
+}
+
+impl RetryBudget {
+    pub fn new(total: u32) -> Self {
+        Self {
+            remaining: Arc::new(AtomicU32::new(total)),
+        }
+    }
+
+    /// Take one retry out of the budget, returning `false` if none are left.
+    pub fn try_acquire(&self) -> bool {
+        self.remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| {
+                remaining.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.remaining.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer, github_spec};
+    use crate::{DownloadFile, GithubFileDownloader};
+
+    #[test]
+    fn test_delay_for_is_exponential() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(100));
+        assert_eq!(policy.delay_for(0), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(1), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_retry_after_seconds_and_date() {
+        let headers = |value: &str| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(reqwest::header::RETRY_AFTER, value.parse().unwrap());
+            headers
+        };
+        assert_eq!(retry_after(&headers("3")), Some(Duration::from_secs(3)));
+        assert_eq!(
+            retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(Duration::ZERO)
+        );
+        let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(120));
+        assert!(retry_after(&headers(&later)).unwrap() > Duration::from_secs(100));
+        assert_eq!(retry_after(&headers("soon")), None);
+        assert_eq!(retry_after(&reqwest::header::HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_is_respected_and_client_errors_fail_fast() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/org/limited/refs/heads/main/docker-compose.yml" if request.hit == 1 => {
+                MockResponse::new(429, "slow down").with_header("retry-after", "1")
+            }
+            "/org/limited/refs/heads/main/docker-compose.yml" => MockResponse::ok("services: {}"),
+            _ => MockResponse::new(404, "Not Found"),
+        })
+        .await;
+        // The backoff alone would retry right away, so any wait comes from `Retry-After`.
+        let downloader = GithubFileDownloader::new()
+            .with_raw_host(server.url())
+            .with_retry_policy(RetryPolicy::new(3, Duration::from_millis(1)));
+        let spec = |repository: &str| github_spec(repository, "docker-compose.yml");
+
+        let started = std::time::Instant::now();
+        assert!(downloader.download_file(&spec("limited")).await.is_ok());
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert!(downloader.download_file(&spec("missing")).await.is_err());
+
+        assert_eq!(
+            server.hits("/org/limited/refs/heads/main/docker-compose.yml"),
+            2
+        );
+        assert_eq!(
+            server.hits("/org/missing/refs/heads/main/docker-compose.yml"),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_beyond_the_cap_fails_right_away() {
+        let server = MockServer::start(|_| {
+            MockResponse::new(503, "come back tomorrow").with_header("retry-after", "86400")
+        })
+        .await;
+        let downloader = GithubFileDownloader::new()
+            .with_raw_host(server.url())
+            .with_retry_policy(
+                RetryPolicy::new(3, Duration::from_millis(1)).with_max_wait(Duration::from_secs(5)),
+            );
+        let spec = github_spec("busy", "docker-compose.yml");
+
+        let started = std::time::Instant::now();
+        assert!(downloader.download_file(&spec).await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            server.hits("/org/busy/refs/heads/main/docker-compose.yml"),
+            1
+        );
+    }
+
+    #[test]
+    fn test_budget_is_shared_between_clones() {
+        let budget = RetryBudget::new(2);
+        let other = budget.clone();
+        assert!(budget.try_acquire());
+        assert!(other.try_acquire());
+        assert!(!budget.try_acquire());
+        assert_eq!(other.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget_caps_total_retries() {
+        // Every endpoint fails twice before it starts serving the file.
+        let server = MockServer::start(|request| {
+            if request.hit <= 2 {
+                MockResponse::new(503, "unavailable")
+            } else {
+                MockResponse::ok("services: {}")
+            }
+        })
+        .await;
+
+        let budget = RetryBudget::new(3);
+        let downloader = GithubFileDownloader::new()
+            .with_raw_host(server.url())
+            .with_retry_policy(RetryPolicy::new(5, Duration::from_millis(1)))
+            .with_retry_budget(budget.clone());
+
+        let specs = ["a", "b", "c"].map(|repository| github_spec(repository, "docker-compose.yml"));
+
+        // The first endpoint recovers using two retries from the budget.
+        assert!(downloader.download_file(&specs[0]).await.is_ok());
+        // The second gets the last retry, which isn't enough.
+        assert!(downloader.download_file(&specs[1]).await.is_err());
+        // The third fails fast since the budget is exhausted.
+        assert!(downloader.download_file(&specs[2]).await.is_err());
+
+        assert_eq!(budget.remaining(), 0);
+        assert_eq!(server.hits("/org/a/refs/heads/main/docker-compose.yml"), 3);
+        assert_eq!(server.hits("/org/b/refs/heads/main/docker-compose.yml"), 2);
+        assert_eq!(server.hits("/org/c/refs/heads/main/docker-compose.yml"), 1);
+        assert_eq!(server.total_hits(), 3 + 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_predicate_limits_retried_statuses() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/org/unavailable/refs/heads/main/docker-compose.yml" if request.hit == 1 => {
+                MockResponse::new(503, "unavailable")
+            }
+            "/org/unavailable/refs/heads/main/docker-compose.yml" => {
+                MockResponse::ok("services: {}")
+            }
+            _ => MockResponse::new(500, "broken"),
+        })
+        .await;
+        let downloader = GithubFileDownloader::new()
+            .with_raw_host(server.url())
+            .with_retry_policy(RetryPolicy::new(3, Duration::from_millis(1)))
+            .with_retry_predicate(|status| status.as_u16() == 503);
+        let spec = |repository: &str| github_spec(repository, "docker-compose.yml");
+
+        assert!(downloader.download_file(&spec("unavailable")).await.is_ok());
+        assert!(downloader.download_file(&spec("broken")).await.is_err());
+
+        assert_eq!(
+            server.hits("/org/unavailable/refs/heads/main/docker-compose.yml"),
+            2
+        );
+        assert_eq!(
+            server.hits("/org/broken/refs/heads/main/docker-compose.yml"),
+            1
+        );
+    }
+}
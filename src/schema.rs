@@ -0,0 +1,224 @@
+use crate::{DockerComposeFile, YammerError};
+use serde::Serialize;
+use serde_yaml::Value;
+use std::sync::LazyLock;
+
+/// The generation of the compose file format a file is written against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaVersion {
+    /// The legacy format, with services declared at the top level and no `version`.
+    V1,
+    /// `version: "2.x"`.
+    V2,
+    /// `version: "3.x"`, which added swarm-only keys like `deploy`.
+    V3,
+    /// The versionless Compose Specification.
+    Spec,
+}
+
+/// Service keys that only swarm-aware (v3 and later) files use.
+const SWARM_ONLY_SERVICE_KEYS: &[&str] = &["deploy", "configs", "secrets"];
+
+impl DockerComposeFile {
+    /// Classify which compose file format this file targets.
+    ///
+    /// An explicit `version` decides between v1, v2 and v3. Without a usable `version`, a file
+    /// with a `services` mapping follows the Compose Specification (or v3 if the version was
+    /// unrecognizable but services use swarm-only keys), and a file without one is v1.
+    pub fn detected_version(&self) -> SchemaVersion {
+        let major = self
+            .version
+            .as_deref()
+            .and_then(|version| version.trim().split('.').next())
+            .and_then(|major| major.parse::<u32>().ok());
+
+        match major {
+            Some(1) => SchemaVersion::V1,
+            Some(2) => SchemaVersion::V2,
+            Some(3) => SchemaVersion::V3,
+            _ if self.services.is_none() && !self.other.is_empty() => SchemaVersion::V1,
+            _ if self.version.is_some() && self.uses_swarm_only_keys() => SchemaVersion::V3,
+            _ => SchemaVersion::Spec,
+        }
+    }
+
+    fn uses_swarm_only_keys(&self) -> bool {
+        self.services
+            .iter()
+            .flat_map(|services| services.values())
+            .any(|service| {
+                SWARM_ONLY_SERVICE_KEYS
+                    .iter()
+                    .any(|key| service.get(key).is_some_and(|value| !value.is_null()))
+            })
+            || ["configs", "secrets"]
+                .iter()
+                .any(|key| self.other.get(key).is_some_and(Value::is_mapping))
+    }
+}
+
+/// The Compose Specification JSON schema, embedded so validation works offline. It's trimmed
+/// down to the top-level sections and the service keys, which is what merging touches, and
+/// doesn't reject unknown keys, so a key it leaves out is never reported as a violation.
+///
+/// It comes from `schema/compose-spec.json` in the compose-spec/compose-spec repository, see
+/// its `$comment` for how to update it.
+const COMPOSE_SPEC_SCHEMA: &str = include_str!("compose-spec.json");
+
+static COMPOSE_SPEC_VALIDATOR: LazyLock<jsonschema::Validator> = LazyLock::new(|| {
+    let schema = serde_json::from_str(COMPOSE_SPEC_SCHEMA)
+        .expect("the embedded compose spec schema should be valid JSON");
+    jsonschema::validator_for(&schema).expect("the embedded compose spec schema should compile")
+});
+
+/// A part of a compose file that doesn't follow the Compose Specification schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// A JSON pointer to the offending value, e.g. `/services/web/ports`.
+    pub pointer: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pointer = if self.pointer.is_empty() {
+            "/"
+        } else {
+            &self.pointer
+        };
+        write!(f, "{pointer}: {}", self.message)
+    }
+}
+
+/// Check compose file contents against the embedded Compose Specification schema, converting
+/// them from YAML to JSON first.
+pub fn schema_violations(contents: &impl Serialize) -> Result<Vec<SchemaViolation>, YammerError> {
+    let instance = serde_json::to_value(contents)?;
+    Ok(COMPOSE_SPEC_VALIDATOR
+        .iter_errors(&instance)
+        .map(|error| SchemaViolation {
+            pointer: error.instance_path().to_string(),
+            message: error.to_string(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> DockerComposeFile {
+        serde_yaml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn test_detected_version_v1() {
+        let file = parse(
+            r#"
+            web:
+              image: nginx
+              links:
+                - db
+            db:
+              image: postgres"#,
+        );
+        assert_eq!(file.detected_version(), SchemaVersion::V1);
+    }
+
+    #[test]
+    fn test_detected_version_v2_and_v3() {
+        let file = parse(
+            r#"
+            version: "2.4"
+            services:
+              web:
+                image: nginx"#,
+        );
+        assert_eq!(file.detected_version(), SchemaVersion::V2);
+
+        let file = parse(
+            r#"
+            version: "3.8"
+            services:
+              web:
+                image: nginx"#,
+        );
+        assert_eq!(file.detected_version(), SchemaVersion::V3);
+    }
+
+    #[test]
+    fn test_detected_version_unrecognized_version_with_swarm_keys() {
+        let file = parse(
+            r#"
+            version: "latest"
+            services:
+              web:
+                image: nginx
+                deploy:
+                  replicas: 2"#,
+        );
+        assert_eq!(file.detected_version(), SchemaVersion::V3);
+    }
+
+    #[test]
+    fn test_detected_version_versionless() {
+        let file = parse(
+            r#"
+            services:
+              web:
+                image: nginx
+                deploy:
+                  replicas: 2"#,
+        );
+        assert_eq!(file.detected_version(), SchemaVersion::Spec);
+    }
+
+    #[test]
+    fn test_schema_violations() {
+        let valid: Value = serde_yaml::from_str(
+            r#"
+            services:
+              web:
+                image: nginx
+                ports: ["8080:80"]
+                x-note: anything
+            networks:
+              default: {}"#,
+        )
+        .unwrap();
+        assert_eq!(schema_violations(&valid).unwrap(), vec![]);
+
+        let unlisted: Value = serde_yaml::from_str(
+            r#"
+            services:
+              web:
+                image: nginx
+                some_newer_key: true
+                healthcheck:
+                  test: [CMD, "true"]
+                  some_newer_option: 1
+            some_newer_section: {}"#,
+        )
+        .unwrap();
+        assert_eq!(schema_violations(&unlisted).unwrap(), vec![]);
+
+        let invalid: Value = serde_yaml::from_str(
+            r#"
+            services:
+              web:
+                image: nginx
+                ports: 8080"#,
+        )
+        .unwrap();
+        let violations = schema_violations(&invalid).unwrap();
+        assert_eq!(violations.len(), 1, "{violations:?}");
+        assert_eq!(violations[0].pointer, "/services/web/ports");
+        assert!(
+            violations[0]
+                .to_string()
+                .starts_with("/services/web/ports: "),
+            "{}",
+            violations[0]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use crate::EnvResolver;
+use serde_yaml::{Mapping, Value};
+
+/// Replace every top-level secret backed by an `environment` variable with an inline
+/// `content` secret holding the variable's value, so the output doesn't depend on the
+/// environment it's run in.
+///
+/// Secrets whose variable isn't set are left as they are, and their variables are returned.
+pub fn inline_env_secrets(contents: &mut Mapping, resolver: &EnvResolver) -> Vec<String> {
+    let mut missing = vec![];
+    let Some(Value::Mapping(secrets)) = contents.get_mut("secrets") else {
+        return missing;
+    };
+    for secret in secrets.values_mut() {
+        let Some(variable) = secret.get("environment").and_then(Value::as_str) else {
+            continue;
+        };
+        match resolver.get(variable) {
+            Some(value) => {
+                let mut inlined = Mapping::new();
+                inlined.insert("content".into(), value.into());
+                *secret = Value::Mapping(inlined);
+            }
+            None => missing.push(variable.to_string()),
+        }
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyValue;
+
+    #[test]
+    fn test_inline_env_secrets() {
+        let mut contents: Mapping = serde_yaml::from_str(
+            r#"
+            services:
+              api:
+                secrets: [db_password, api_key, tls_cert]
+            secrets:
+              db_password:
+                environment: DB_PASSWORD
+              api_key:
+                environment: API_KEY
+              tls_cert:
+                file: ./cert.pem"#,
+        )
+        .unwrap();
+        let resolver = EnvResolver::new()
+            .with_overrides(&["DB_PASSWORD=hunter2".parse::<KeyValue>().unwrap()]);
+
+        let missing = inline_env_secrets(&mut contents, &resolver);
+
+        assert_eq!(missing, vec!["API_KEY"]);
+        let secrets = &contents[&Value::from("secrets")];
+        assert_eq!(
+            secrets["db_password"],
+            serde_yaml::from_str::<Value>("content: hunter2").unwrap()
+        );
+        assert_eq!(secrets["api_key"]["environment"], "API_KEY");
+        assert_eq!(secrets["tls_cert"]["file"], "./cert.pem");
+    }
+}
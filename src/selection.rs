@@ -0,0 +1,547 @@
+use crate::{ComposeServiceSpec, DockerComposeFile, YammerError};
+use regex::Regex;
+use serde_yaml::{Mapping, Value};
+use std::path::Path;
+use std::str::FromStr;
+
+/// A service to pull out of a compose file, and the name to merge it under.
+///
+/// Parsed from `name`, or `name=newname` to rename the service on the way in, e.g. to keep
+/// the `postgres` services of two repositories apart. Either can be followed by changes to
+/// the service's fields, like `postgres{image=postgres:16,ports=-}`, see [`FieldPatch`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ServiceSelection {
+    /// The name of the service in the compose file it comes from.
+    pub source_name: String,
+    /// The name of the service in the merged output.
+    pub target_name: String,
+    /// Changes to the service's fields, applied in order.
+    pub patches: Vec<FieldPatch>,
+}
+
+/// A change to a top-level field of a selected service, parsed from `field=value`, or
+/// `field=-` to remove the field.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FieldPatch {
+    pub field: String,
+    /// The field's new value as YAML, or `None` to remove it.
+    pub value: Option<String>,
+}
+
+impl FieldPatch {
+    pub fn set(field: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            value: Some(value.into()),
+        }
+    }
+
+    pub fn remove(field: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            value: None,
+        }
+    }
+
+    /// Set or remove the field in `service`.
+    pub fn apply(&self, service: &mut Mapping) -> Result<(), YammerError> {
+        match &self.value {
+            Some(value) => {
+                let value: Value = serde_yaml::from_str(value).map_err(|err| {
+                    YammerError::UnknownSpec(format!(
+                        "the value given to `{}` is not valid YAML: {err}",
+                        self.field
+                    ))
+                })?;
+                service.insert(self.field.as_str().into(), value);
+            }
+            None => {
+                service.remove(self.field.as_str());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for FieldPatch {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let patch = s
+            .split_once('=')
+            .map(|(field, value)| (field.trim(), value.trim()));
+        match patch {
+            Some((field, "-")) if !field.is_empty() => Ok(Self::remove(field)),
+            Some((field, value)) if !field.is_empty() => Ok(Self::set(field, value)),
+            _ => Err(YammerError::UnknownSpec(format!(
+                "expected <field>=<value> or <field>=-, got `{s}`"
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldPatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.field, self.value.as_deref().unwrap_or("-"))
+    }
+}
+
+impl ServiceSelection {
+    /// Select a service without renaming it.
+    pub fn new(name: impl Into<String>) -> Self {
+        let name = name.into();
+        Self {
+            source_name: name.clone(),
+            target_name: name,
+            patches: vec![],
+        }
+    }
+
+    /// Merge the service under `target_name` instead of its own name.
+    pub fn renamed(mut self, target_name: impl Into<String>) -> Self {
+        self.target_name = target_name.into();
+        self
+    }
+
+    /// Change a field of the service on the way in.
+    pub fn patched(mut self, patch: FieldPatch) -> Self {
+        self.patches.push(patch);
+        self
+    }
+
+    pub fn is_renamed(&self) -> bool {
+        self.source_name != self.target_name
+    }
+
+    /// Apply the selection's patches to the service, in order.
+    pub fn apply_patches(&self, service: &mut Mapping) -> Result<(), YammerError> {
+        self.patches
+            .iter()
+            .try_for_each(|patch| patch.apply(service))
+    }
+}
+
+impl From<String> for ServiceSelection {
+    fn from(name: String) -> Self {
+        Self::new(name)
+    }
+}
+
+impl FromStr for ServiceSelection {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, patches) = match s.split_once('{') {
+            Some((name, patches)) => {
+                let Some(patches) = patches.strip_suffix('}') else {
+                    return Err(YammerError::UnknownSpec(format!(
+                        "expected <service>{{<field>=<value>,...}}, got `{s}`"
+                    )));
+                };
+                let patches = split_top_level(patches)
+                    .into_iter()
+                    .filter(|patch| !patch.trim().is_empty())
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?;
+                (name, patches)
+            }
+            None => (s, vec![]),
+        };
+        let selection = match name.split_once('=') {
+            None => {
+                validate_service_name(name.trim(), true)?;
+                Self::new(name.trim())
+            }
+            Some((source, target)) if !source.trim().is_empty() && !target.trim().is_empty() => {
+                let (source, target) = (source.trim(), target.trim());
+                if is_glob(source) {
+                    return Err(YammerError::UnknownSpec(format!(
+                        "`{source}` is a pattern, which can't be renamed since it may match more than one service"
+                    )));
+                }
+                validate_service_name(source, true)?;
+                validate_service_name(target, false)?;
+                Self::new(source).renamed(target)
+            }
+            Some(_) => {
+                return Err(YammerError::UnknownSpec(format!(
+                    "expected <service>=<new name>, got `{s}`"
+                )));
+            }
+        };
+        if is_glob(&selection.source_name) && !patches.is_empty() {
+            return Err(YammerError::UnknownSpec(format!(
+                "`{}` is a pattern, which can't be patched, only services selected by name can",
+                selection.source_name
+            )));
+        }
+        Ok(Self {
+            patches,
+            ..selection
+        })
+    }
+}
+
+/// Formats the selection back into the form it can be parsed from.
+impl std::fmt::Display for ServiceSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source_name)?;
+        if self.is_renamed() {
+            write!(f, "={}", self.target_name)?;
+        }
+        if !self.patches.is_empty() {
+            let patches: Vec<String> = self.patches.iter().map(ToString::to_string).collect();
+            write!(f, "{{{}}}", patches.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that `name` is a service name compose accepts: letters, digits, `.`, `_` and `-`.
+/// Names that select services (`pattern`) may also contain the `*`s of a glob, see
+/// [`DockerComposeFile::matching_services`].
+pub fn validate_service_name(name: &str, pattern: bool) -> Result<(), YammerError> {
+    let valid = |character: char| {
+        character.is_ascii_alphanumeric()
+            || matches!(character, '.' | '_' | '-')
+            || pattern && character == '*'
+    };
+    if name.is_empty() || !name.chars().all(valid) {
+        return Err(YammerError::InvalidServiceName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Parse the comma separated services of a DSN, skipping empty entries. The commas between
+/// the `{...}` patches of a service don't separate services.
+pub fn parse_service_selections(csv: &str) -> Result<Vec<ServiceSelection>, YammerError> {
+    split_top_level(csv)
+        .into_iter()
+        .map(str::trim)
+        .filter(|service| !service.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Split `csv` at the commas that aren't inside `{...}` or `[...]`.
+fn split_top_level(csv: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (index, character) in csv.char_indices() {
+        match character {
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&csv[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&csv[start..]);
+    parts
+}
+
+/// The names the selected services have in the compose file they come from.
+pub fn source_names(selections: &[ServiceSelection]) -> Vec<String> {
+    selections
+        .iter()
+        .map(|selection| selection.source_name.clone())
+        .collect()
+}
+
+/// Parse a list of services to select, one per line, each written the way it would be after
+/// the `@` of a spec, so it can be renamed or patched too.
+///
+/// Blank lines are skipped, and anything after a `#` is a comment. A line that isn't a valid
+/// selection is an error naming the line.
+pub fn parse_service_list(contents: &str) -> Result<Vec<ServiceSelection>, YammerError> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let line = line.split_once('#').map_or(line, |(line, _)| line).trim();
+            (index + 1, line)
+        })
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(number, line)| {
+            line.parse()
+                .map_err(|err| YammerError::UnknownSpec(format!("line {number}: {err}")))
+        })
+        .collect()
+}
+
+/// Read a list of service names to select from a file. See [`parse_service_list`].
+pub fn read_include_file(path: impl AsRef<Path>) -> Result<Vec<ServiceSelection>, YammerError> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path).map_err(|source| YammerError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_service_list(&contents)
+        .map_err(|err| YammerError::UnknownSpec(format!("{}: {err}", path.display())))
+}
+
+/// Select the services listed in the include file at `path` (see [`read_include_file`]) for
+/// every spec that doesn't list its own. The file isn't read if every spec does.
+pub fn include_services(
+    specs: &mut [ComposeServiceSpec],
+    path: impl AsRef<Path>,
+) -> Result<(), YammerError> {
+    if specs.iter().all(|spec| !spec.services.is_empty()) {
+        return Ok(());
+    }
+    let included = read_include_file(path)?;
+    for spec in specs.iter_mut().filter(|spec| spec.services.is_empty()) {
+        spec.services = included.clone();
+    }
+    Ok(())
+}
+
+/// Whether a selected service name is a pattern, with `*` standing for any run of characters.
+pub fn is_glob(name: &str) -> bool {
+    name.contains('*')
+}
+
+impl DockerComposeFile {
+    /// The services whose names match `pattern`, in the order this file defines them.
+    ///
+    /// A `*` in the pattern matches any run of characters, so `worker-*` matches `worker-1`
+    /// and `worker-2`, and a bare `*` matches every service.
+    pub fn matching_services(&self, pattern: &str) -> Vec<String> {
+        let pattern: Vec<String> = pattern.split('*').map(regex::escape).collect();
+        let regex = Regex::new(&format!("^{}$", pattern.join(".*")))
+            .expect("an escaped pattern is a valid regex");
+        self.service_names()
+            .filter(|name| regex.is_match(name))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// Order the selected service names by where they're defined in `compose_file`, rather than
+/// the order they were selected in. Names the file doesn't define keep their relative order
+/// at the end.
+pub fn order_by_source<'a>(
+    services: &'a [String],
+    compose_file: &DockerComposeFile,
+) -> Vec<&'a String> {
+    let position = |name: &String| {
+        compose_file
+            .services
+            .as_ref()
+            .and_then(|defined| defined.keys().position(|key| key.as_str() == Some(name)))
+            .unwrap_or(usize::MAX)
+    };
+    let mut ordered: Vec<&String> = services.iter().collect();
+    ordered.sort_by_key(|name| position(name));
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer, TempDir};
+    use crate::{
+        ComposeServiceSpec, DownloadFile, GithubFileDownloader, SourceDownloader, parse_spec,
+    };
+
+    #[test]
+    fn test_parse_service_list() {
+        let contents = "# databases\npostgres\n\n  redis  # cache\n#mongo\nweb=frontend{ports=-}\n";
+        assert_eq!(
+            parse_service_list(contents).unwrap(),
+            vec![
+                ServiceSelection::new("postgres"),
+                ServiceSelection::new("redis"),
+                ServiceSelection::new("web")
+                    .renamed("frontend")
+                    .patched(FieldPatch::remove("ports")),
+            ]
+        );
+        for (invalid, line) in [
+            ("postgres\na b\n", "line 2"),
+            ("postgres\n\nFoo!\n", "line 3"),
+        ] {
+            let err = parse_service_list(invalid).unwrap_err();
+            assert!(err.to_string().contains(line), "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_include_file_selects_services() {
+        let server = MockServer::start(|_| {
+            MockResponse::ok(
+                "services:\n  postgres:\n    image: postgres\n  redis:\n    image: redis\n  mongo:\n    image: mongo\n",
+            )
+        })
+        .await;
+        let dir = TempDir::new("include");
+        let path = dir.join("services.txt");
+        std::fs::write(&path, "# what we need\npostgres\nredis # for sessions\n").unwrap();
+
+        let mut specs: Vec<ComposeServiceSpec> = vec![
+            "org/repo+main:docker-compose.yml".parse().unwrap(),
+            "org/repo+main:docker-compose.yml@mongo".parse().unwrap(),
+        ];
+        include_services(&mut specs, &path).unwrap();
+        assert_eq!(source_names(&specs[1].services), ["mongo"]);
+        let spec = &specs[0];
+
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let compose_file = downloader
+            .download_compose_file(&spec.source)
+            .await
+            .unwrap();
+        let selected: Vec<_> = spec
+            .services
+            .iter()
+            .filter_map(|service| compose_file.get_service(&service.source_name))
+            .map(|service| service["image"].as_str().unwrap())
+            .collect();
+        assert_eq!(selected, vec!["postgres", "redis"]);
+    }
+
+    #[test]
+    fn test_spec_renames_services() {
+        let spec = parse_spec("owner/repo:docker-compose.yml@api,postgres=pg_primary").unwrap();
+        assert_eq!(
+            spec.services,
+            vec![
+                ServiceSelection::new("api"),
+                ServiceSelection::new("postgres").renamed("pg_primary"),
+            ]
+        );
+
+        let spec: ComposeServiceSpec = "gist:abc:compose.yml@postgres=pg_replica".parse().unwrap();
+        assert_eq!(spec.target_name("postgres"), "pg_replica");
+        assert_eq!(spec.target_name("api"), "api");
+        assert_eq!(spec.to_string(), "gist:abc:compose.yml@postgres=pg_replica");
+
+        assert!(parse_spec("owner/repo:docker-compose.yml@postgres=").is_err());
+        assert!(parse_spec("owner/repo:docker-compose.yml@=pg").is_err());
+    }
+
+    #[test]
+    fn test_spec_patches_service_fields() {
+        let spec = parse_spec(
+            "owner/repo+main:docker-compose.yml@api,postgres=db{image=postgres:16@sha256:abc,ports=-}",
+        )
+        .unwrap();
+        assert_eq!(spec.spec.filepath, "docker-compose.yml");
+        assert_eq!(
+            spec.services,
+            vec![
+                ServiceSelection::new("api"),
+                ServiceSelection::new("postgres")
+                    .renamed("db")
+                    .patched(FieldPatch::set("image", "postgres:16@sha256:abc"))
+                    .patched(FieldPatch::remove("ports")),
+            ]
+        );
+        assert_eq!(
+            ComposeServiceSpec::from(spec.clone()).to_string(),
+            "owner/repo+main:docker-compose.yml@api,postgres=db{image=postgres:16@sha256:abc,ports=-}"
+        );
+
+        let mut service: Mapping =
+            serde_yaml::from_str("image: postgres:15\nports: [\"5432:5432\"]\nrestart: always\n")
+                .unwrap();
+        spec.services[1].apply_patches(&mut service).unwrap();
+        assert_eq!(
+            service,
+            serde_yaml::from_str::<Mapping>("image: postgres:16@sha256:abc\nrestart: always\n")
+                .unwrap()
+        );
+
+        let spec = parse_spec("owner/repo:docker-compose.yml@api{command=[a,b],ports=-}").unwrap();
+        assert_eq!(
+            spec.services[0].patches,
+            [
+                FieldPatch::set("command", "[a,b]"),
+                FieldPatch::remove("ports")
+            ]
+        );
+
+        assert!(parse_spec("owner/repo:docker-compose.yml@postgres{image}").is_err());
+        assert!(parse_spec("owner/repo:docker-compose.yml@postgres{image=x").is_err());
+        let invalid = ServiceSelection::new("api").patched(FieldPatch::set("ports", "[8080"));
+        assert!(invalid.apply_patches(&mut Mapping::new()).is_err());
+    }
+
+    #[test]
+    fn test_service_names_are_trimmed_and_validated() {
+        let spec = parse_spec("owner/repo:docker-compose.yml@a, b ,c = d{ image = x }").unwrap();
+        assert_eq!(
+            spec.services,
+            vec![
+                ServiceSelection::new("a"),
+                ServiceSelection::new("b"),
+                ServiceSelection::new("c")
+                    .renamed("d")
+                    .patched(FieldPatch::set("image", "x")),
+            ]
+        );
+
+        for name in ["my service", "api!", "db=pg*", "caché"] {
+            assert!(
+                matches!(
+                    name.parse::<ServiceSelection>(),
+                    Err(YammerError::InvalidServiceName(_))
+                ),
+                "{name}"
+            );
+        }
+        assert!("worker-*".parse::<ServiceSelection>().is_ok());
+        assert!(validate_service_name("x-postgres.v2_1", false).is_ok());
+        assert!(validate_service_name("", false).is_err());
+    }
+
+    #[test]
+    fn test_matching_services() {
+        let compose_file = DockerComposeFile::try_from(&bytes::Bytes::from(
+            "services:\n  worker-1:\n    image: w\n  api:\n    image: a\n  worker-2:\n    image: w\n  worker.x:\n    image: w\n",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            compose_file.matching_services("worker-*"),
+            ["worker-1", "worker-2"]
+        );
+        assert_eq!(
+            compose_file.matching_services("*"),
+            ["worker-1", "api", "worker-2", "worker.x"]
+        );
+        assert_eq!(compose_file.matching_services("*.x"), ["worker.x"]);
+        assert!(compose_file.matching_services("db-*").is_empty());
+        assert!(is_glob("worker-*") && !is_glob("worker-1"));
+        let spec = parse_spec("owner/repo:docker-compose.yml@worker-*,api").unwrap();
+        assert_eq!(source_names(&spec.services), ["worker-*", "api"]);
+        assert!(parse_spec("owner/repo:docker-compose.yml@worker-*=worker").is_err());
+        assert!(parse_spec("owner/repo:docker-compose.yml@worker-*{image=w}").is_err());
+    }
+
+    #[test]
+    fn test_read_include_file_missing() {
+        let result = read_include_file("/definitely/not/here");
+        assert!(matches!(result, Err(YammerError::Io { .. })));
+    }
+
+    #[test]
+    fn test_order_by_source() {
+        let compose_file = DockerComposeFile::try_from(&bytes::Bytes::from(
+            "services:\n  a:\n    image: a\n  b:\n    image: b\n  c:\n    image: c\n",
+        ))
+        .unwrap();
+        let spec: ComposeServiceSpec = "org/repo:docker-compose.yml@c,missing,a".parse().unwrap();
+
+        assert_eq!(source_names(&spec.services), ["c", "missing", "a"]);
+        assert_eq!(
+            order_by_source(&source_names(&spec.services), &compose_file),
+            ["a", "c", "missing"]
+        );
+    }
+}
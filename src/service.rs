@@ -0,0 +1,189 @@
+use crate::DockerComposeFile;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+
+/// A service of a compose file, with the fields most of this crate looks at typed, and every
+/// other field kept as it is in [`Service::extra`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Service {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<Build>,
+    /// Ports, in either the short (`"8080:80"`) or the long (`{target: 80}`) syntax.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Environment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<DependsOn>,
+    /// Volumes, in either the short (`./data:/data`) or the long (`{type: bind}`) syntax.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<Value>,
+    /// Every other field of the service.
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+/// A service's `build`: either just its context, or the long syntax.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Build {
+    Context(String),
+    Detailed(Mapping),
+}
+
+/// A service's `environment`: either a mapping, or a list of `KEY=VALUE` entries.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Environment {
+    List(Vec<String>),
+    Map(Mapping),
+}
+
+/// A service's `depends_on`: either a list of names, or a mapping of names to conditions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DependsOn {
+    List(Vec<String>),
+    Map(Mapping),
+}
+
+impl Build {
+    /// The build context, if there is one.
+    pub fn context(&self) -> Option<&str> {
+        match self {
+            Build::Context(context) => Some(context),
+            Build::Detailed(build) => build.get("context").and_then(Value::as_str),
+        }
+    }
+}
+
+impl Environment {
+    /// The variables, whichever syntax they're given in. Variables without a value (like
+    /// `- DEBUG`, or `DEBUG:`) map to `None`.
+    pub fn variables(&self) -> BTreeMap<String, Option<String>> {
+        match self {
+            Environment::List(entries) => entries
+                .iter()
+                .map(|entry| match entry.split_once('=') {
+                    Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                    None => (entry.clone(), None),
+                })
+                .collect(),
+            Environment::Map(variables) => variables
+                .iter()
+                .filter_map(|(key, value)| {
+                    let value = match value {
+                        Value::Null => None,
+                        Value::String(value) => Some(value.clone()),
+                        Value::Bool(value) => Some(value.to_string()),
+                        Value::Number(value) => Some(value.to_string()),
+                        _ => return None,
+                    };
+                    Some((key.as_str()?.to_string(), value))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl DependsOn {
+    /// The names of the services depended on, whichever syntax they're given in.
+    pub fn names(&self) -> Vec<String> {
+        match self {
+            DependsOn::List(names) => names.clone(),
+            DependsOn::Map(conditions) => conditions
+                .keys()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+impl Service {
+    /// The names of the services this one `depends_on`.
+    pub fn dependencies(&self) -> Vec<String> {
+        self.depends_on
+            .as_ref()
+            .map(DependsOn::names)
+            .unwrap_or_default()
+    }
+}
+
+impl DockerComposeFile {
+    /// A service of this file, typed. `None` if there's no such service, or its fields aren't
+    /// of the types compose expects.
+    pub fn typed_service(&self, name: &str) -> Option<Service> {
+        let service = self.get_service(name)?;
+        serde_yaml::from_value(Value::Mapping(service.clone())).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_service() {
+        let compose_file = DockerComposeFile::try_from(&bytes::Bytes::from(
+            r#"
+services:
+  api:
+    image: org/api:1.0
+    build:
+      context: ./api
+      dockerfile: Dockerfile.prod
+    ports: ["8080:80", 9090]
+    environment:
+      PORT: 80
+      DEBUG:
+    depends_on:
+      db:
+        condition: service_healthy
+    restart: always
+  db:
+    build: ./db
+    environment: [POSTGRES_DB=app, TZ]
+    depends_on: [cache]
+    volumes: ["./data:/var/lib/postgresql/data"]
+  broken:
+    image: [not, a, string]
+"#,
+        ))
+        .unwrap();
+
+        let api = compose_file.typed_service("api").unwrap();
+        assert_eq!(api.image.as_deref(), Some("org/api:1.0"));
+        assert_eq!(api.build.as_ref().and_then(Build::context), Some("./api"));
+        assert_eq!(api.ports.len(), 2);
+        assert_eq!(
+            api.environment.as_ref().unwrap().variables(),
+            BTreeMap::from([
+                ("DEBUG".to_string(), None),
+                ("PORT".to_string(), Some("80".to_string())),
+            ])
+        );
+        assert_eq!(api.dependencies(), ["db"]);
+        assert_eq!(api.extra["restart"], "always");
+        assert!(api.volumes.is_empty());
+
+        let db = compose_file.typed_service("db").unwrap();
+        assert!(db.image.is_none());
+        assert_eq!(db.build, Some(Build::Context("./db".to_string())));
+        assert_eq!(
+            db.environment.as_ref().unwrap().variables()["POSTGRES_DB"].as_deref(),
+            Some("app")
+        );
+        assert_eq!(db.dependencies(), ["cache"]);
+        assert_eq!(db.volumes.len(), 1);
+
+        assert!(compose_file.typed_service("broken").is_none());
+        assert!(compose_file.typed_service("missing").is_none());
+
+        let roundtrip: Mapping = serde_yaml::from_value(serde_yaml::to_value(db).unwrap()).unwrap();
+        assert_eq!(&roundtrip, compose_file.get_service("db").unwrap());
+    }
+}
@@ -0,0 +1,1024 @@
+use crate::{
+    CacheKey, ComposeServiceGithubSpec, Conditional, CustomFileSpec, DockerComposeFile,
+    DownloadFile, DownloadProgress, GistFileDownloader, GistFileSpec, GithubFileDownloader,
+    GithubFileSpec, GithubRef, GitlabFileDownloader, GitlabFileSpec, LocalFileDownloader,
+    LocalFileSpec, RawUrlDownloader, RawUrlSpec, ServiceSelection, SourceRegistry, YammerError,
+    ZipFileDownloader, ZipFileSpec, parse_service_selections, split_services,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{StreamExt, stream};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Where a compose file comes from.
+#[derive(Debug, Clone)]
+pub enum FileSource {
+    Github(GithubFileSpec<String>),
+    Gitlab(GitlabFileSpec<String>),
+    Gist(GistFileSpec),
+    Zip(ZipFileSpec),
+    /// A file on disk, from a `file://` DSN.
+    Local(LocalFileSpec),
+    /// A file at an `http://` or `https://` url.
+    Url(RawUrlSpec),
+    /// A file from a scheme registered with [`crate::SourceRegistry::with_scheme`].
+    Custom(CustomFileSpec),
+}
+
+impl std::fmt::Display for FileSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileSource::Github(spec) => write!(f, "{}", spec.get_url()),
+            FileSource::Gitlab(spec) => write!(f, "{}", spec.get_url()),
+            FileSource::Gist(spec) => write!(f, "gist:{}:{}", spec.id, spec.filename),
+            FileSource::Zip(spec) => write!(f, "zip:{}!{}", spec.url, spec.entry),
+            FileSource::Local(spec) => write!(f, "file://{}", spec.path.display()),
+            FileSource::Url(spec) => write!(f, "{}", spec.url),
+            FileSource::Custom(spec) => write!(f, "{}:{}", spec.scheme, spec.path),
+        }
+    }
+}
+
+impl FileSource {
+    /// The file at a path relative to this one, from the same source.
+    ///
+    /// Gists have no directories, so only the file name of the path is kept for them.
+    pub fn resolve_relative(&self, relative: &str) -> FileSource {
+        match self {
+            FileSource::Github(spec) => FileSource::Github(spec.resolve_relative(relative)),
+            FileSource::Gitlab(spec) => FileSource::Gitlab(GitlabFileSpec::new(
+                spec.project.clone(),
+                spec.branch.clone(),
+                join_relative(&spec.filepath, relative),
+            )),
+            FileSource::Gist(spec) => FileSource::Gist(GistFileSpec::new(
+                spec.id.clone(),
+                relative.rsplit('/').next().unwrap_or(relative),
+            )),
+            FileSource::Zip(spec) => FileSource::Zip(ZipFileSpec::new(
+                spec.url.clone(),
+                join_relative(&spec.entry, relative),
+            )),
+            FileSource::Local(spec) => FileSource::Local(LocalFileSpec::new(
+                spec.path
+                    .parent()
+                    .map_or_else(|| relative.into(), |dir| dir.join(relative)),
+            )),
+            FileSource::Url(spec) => {
+                FileSource::Url(RawUrlSpec::new(join_relative(&spec.url, relative)))
+            }
+            FileSource::Custom(spec) => FileSource::Custom(CustomFileSpec::new(
+                spec.scheme.clone(),
+                join_relative(&spec.path, relative),
+            )),
+        }
+    }
+
+    /// The path of the file within its repository or gist. For files at a url, the url.
+    pub fn path(&self) -> &str {
+        match self {
+            FileSource::Github(spec) => &spec.filepath,
+            FileSource::Gitlab(spec) => &spec.filepath,
+            FileSource::Gist(spec) => &spec.filename,
+            FileSource::Zip(spec) => &spec.entry,
+            FileSource::Local(spec) => spec.path.to_str().unwrap_or_default(),
+            FileSource::Url(spec) => &spec.url,
+            FileSource::Custom(spec) => &spec.path,
+        }
+    }
+
+    /// Another file next to this one: from the same repository (on the same branch), gist or
+    /// archive, by its path in there.
+    ///
+    /// Local paths and urls can be given relative to this file, like an override file in the
+    /// same directory by just its name.
+    pub fn sibling(&self, path: &str) -> FileSource {
+        match self {
+            FileSource::Local(spec) => FileSource::Local(LocalFileSpec::new(
+                spec.path.parent().unwrap_or(Path::new("")).join(path),
+            )),
+            FileSource::Url(spec) if !path.contains("://") => {
+                FileSource::Url(RawUrlSpec::new(join_relative(&spec.url, path)))
+            }
+            _ => self.with_path(path),
+        }
+    }
+
+    /// The same source for the file at `path` instead, taken as it is.
+    pub fn with_path(&self, path: &str) -> FileSource {
+        match self {
+            FileSource::Github(spec) => FileSource::Github(
+                GithubFileSpec::new(
+                    spec.project.clone(),
+                    spec.repository.clone(),
+                    spec.branch.clone(),
+                    path.to_string(),
+                )
+                .with_ref(spec.reference),
+            ),
+            FileSource::Gitlab(spec) => FileSource::Gitlab(GitlabFileSpec::new(
+                spec.project.clone(),
+                spec.branch.clone(),
+                path.to_string(),
+            )),
+            FileSource::Gist(spec) => FileSource::Gist(GistFileSpec::new(spec.id.clone(), path)),
+            FileSource::Zip(spec) => FileSource::Zip(ZipFileSpec::new(spec.url.clone(), path)),
+            FileSource::Local(_) => FileSource::Local(LocalFileSpec::new(path)),
+            FileSource::Url(_) => FileSource::Url(RawUrlSpec::new(path)),
+            FileSource::Custom(spec) => {
+                FileSource::Custom(CustomFileSpec::new(spec.scheme.clone(), path))
+            }
+        }
+    }
+}
+
+/// The path of `relative` resolved against the directory of the file at `path`.
+pub(crate) fn join_relative(path: &str, relative: &str) -> String {
+    let mut segments: Vec<&str> = path.split('/').collect();
+    segments.pop();
+    for segment in relative.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+impl CacheKey for FileSource {
+    fn cache_key(&self) -> String {
+        match self {
+            FileSource::Github(spec) => spec.cache_key(),
+            FileSource::Gitlab(spec) => spec.cache_key(),
+            FileSource::Gist(_)
+            | FileSource::Zip(_)
+            | FileSource::Local(_)
+            | FileSource::Url(_)
+            | FileSource::Custom(_) => self.to_string(),
+        }
+    }
+
+    fn is_immutable(&self) -> bool {
+        match self {
+            FileSource::Github(spec) => spec.is_immutable(),
+            FileSource::Gitlab(spec) => spec.is_immutable(),
+            _ => false,
+        }
+    }
+
+    fn is_cacheable(&self) -> bool {
+        !matches!(self, FileSource::Local(_))
+    }
+}
+
+/// A set of services to pull out of a compose file from any supported source.
+///
+/// Besides the Github DSN understood by [`ComposeServiceGithubSpec`], this accepts
+/// `gist:<id>:<filename>@<services>` for a file in a GitHub Gist,
+/// `gitlab:<group>/<project>+<branch>:<path>@<services>` for a file on GitLab,
+/// `file://<path>@<services>` for a file on disk, `https://<url>@<services>` for a file at any
+/// url, and the DSNs of any other scheme in the [`SourceRegistry`] it's parsed with.
+///
+/// Either form can list override files after the first one, separated by commas, e.g.
+/// `org/repo:docker-compose.yml,docker-compose.override.yml@web`. They're layered on top of
+/// the first file like `docker compose -f` would, before any services are selected.
+#[derive(Debug, Clone)]
+pub struct ComposeServiceSpec {
+    pub source: FileSource,
+    /// Files from the same repository or gist to layer on top of `source`, in order.
+    pub overrides: Vec<FileSource>,
+    pub services: Vec<ServiceSelection>,
+}
+
+/// Identifies one of the specs given to a run, by its position among them.
+///
+/// The spec's DSN is kept along for display.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct SpecId {
+    pub index: usize,
+    pub spec: String,
+}
+
+impl std::fmt::Display for SpecId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+impl ComposeServiceSpec {
+    /// The id of this spec, given its position among the specs of a run.
+    pub fn id(&self, index: usize) -> SpecId {
+        SpecId {
+            index,
+            spec: self.to_string(),
+        }
+    }
+
+    /// The name a service of this spec's file is merged under: the name it's renamed to if
+    /// it's selected as `service=newname`, otherwise its own.
+    pub fn target_name(&self, source_name: &str) -> String {
+        self.selection(source_name)
+            .map_or(source_name, |selection| &selection.target_name)
+            .to_string()
+    }
+
+    /// How a service of this spec's file is selected, if it's selected by name.
+    pub fn selection(&self, source_name: &str) -> Option<&ServiceSelection> {
+        self.services
+            .iter()
+            .find(|selection| selection.source_name == source_name)
+    }
+
+    /// Every file this spec needs: the source, followed by its overrides.
+    pub fn files(&self) -> impl Iterator<Item = &FileSource> {
+        std::iter::once(&self.source).chain(&self.overrides)
+    }
+}
+
+/// Formats the spec back into the DSN it can be parsed from.
+impl std::fmt::Display for ComposeServiceSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            FileSource::Github(spec) => {
+                write!(f, "{}/{}", spec.project, spec.repository)?;
+                if spec.reference != GithubRef::DefaultBranch {
+                    write!(f, "+{}{}", spec.reference.marker(), spec.branch)?;
+                }
+                if !spec.filepath.is_empty() {
+                    write!(f, ":{}", spec.filepath)?;
+                }
+            }
+            FileSource::Gitlab(spec) => write!(
+                f,
+                "gitlab:{}+{}:{}",
+                spec.project, spec.branch, spec.filepath
+            )?,
+            FileSource::Gist(spec) => write!(f, "gist:{}:{}", spec.id, spec.filename)?,
+            FileSource::Zip(spec) => write!(f, "zip:{}!{}", spec.url, spec.entry)?,
+            FileSource::Local(_) | FileSource::Url(_) => write!(f, "{}", self.source)?,
+            FileSource::Custom(spec) => write!(f, "{}:{}", spec.scheme, spec.path)?,
+        }
+        for layer in &self.overrides {
+            write!(f, ",{}", layer.path())?;
+        }
+        if !self.services.is_empty() {
+            let services: Vec<String> = self.services.iter().map(ToString::to_string).collect();
+            write!(f, "@{}", services.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ComposeServiceGithubSpec<String>> for ComposeServiceSpec {
+    fn from(value: ComposeServiceGithubSpec<String>) -> Self {
+        Self {
+            source: FileSource::Github(value.spec),
+            overrides: vec![],
+            services: value.services,
+        }
+    }
+}
+
+impl FromStr for ComposeServiceSpec {
+    type Err = YammerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SourceRegistry::default().parse(s)
+    }
+}
+
+pub(crate) fn parse_gitlab_spec(gitlab: &str) -> Result<ComposeServiceSpec, YammerError> {
+    let (file, services_csv) = split_services(gitlab);
+    Ok(ComposeServiceSpec {
+        source: FileSource::Gitlab(file.parse()?),
+        overrides: vec![],
+        services: parse_service_selections(services_csv)?,
+    })
+}
+
+pub(crate) fn parse_local_spec(local: &str) -> Result<ComposeServiceSpec, YammerError> {
+    let Some(path) = local.strip_prefix("//") else {
+        return Err(YammerError::UnknownSpec(
+            "expected file://<path>@<services>".to_string(),
+        ));
+    };
+    let (path, services_csv) = split_services(path);
+    Ok(ComposeServiceSpec {
+        source: FileSource::Local(LocalFileSpec::new(path)),
+        overrides: vec![],
+        services: parse_service_selections(services_csv)?,
+    })
+}
+
+/// Parse `http(s)://<url>@<services>`. The services are split off at the last `@` outside
+/// of `{...}`, so a url with credentials in it needs its services listed too.
+pub(crate) fn parse_url_spec(url: &str) -> Result<ComposeServiceSpec, YammerError> {
+    let (url, services_csv) = split_services(url);
+    Ok(ComposeServiceSpec {
+        source: FileSource::Url(RawUrlSpec::new(url)),
+        overrides: vec![],
+        services: parse_service_selections(services_csv)?,
+    })
+}
+
+pub(crate) fn parse_gist_spec(gist: &str) -> Result<ComposeServiceSpec, YammerError> {
+    let (file, services_csv) = split_services(gist);
+    let Some((id, filename)) = file.split_once(':') else {
+        return Err(YammerError::UnknownSpec(
+            "expected gist:<id>:<filename>@<services>".to_string(),
+        ));
+    };
+    if id.is_empty() || filename.is_empty() {
+        return Err(YammerError::UnknownSpec(
+            "expected gist:<id>:<filename>@<services>".to_string(),
+        ));
+    }
+    Ok(ComposeServiceSpec {
+        source: FileSource::Gist(GistFileSpec::new(id, filename)),
+        overrides: vec![],
+        services: parse_service_selections(services_csv)?,
+    })
+}
+
+pub(crate) fn parse_zip_spec(zip: &str) -> Result<ComposeServiceSpec, YammerError> {
+    let (zip, services_csv) = split_services(zip);
+    let Some((url, entry)) = zip.rsplit_once('!') else {
+        return Err(YammerError::UnknownSpec(
+            "expected zip:<url>!<path in archive>@<services>".to_string(),
+        ));
+    };
+    if url.is_empty() || entry.is_empty() {
+        return Err(YammerError::UnknownSpec(
+            "expected zip:<url>!<path in archive>@<services>".to_string(),
+        ));
+    }
+    Ok(ComposeServiceSpec {
+        source: FileSource::Zip(ZipFileSpec::new(url, entry)),
+        overrides: vec![],
+        services: parse_service_selections(services_csv)?,
+    })
+}
+
+/// Downloads from whichever source a [`FileSource`] points at.
+#[derive(Debug, Clone, Default)]
+pub struct SourceDownloader {
+    pub github: GithubFileDownloader,
+    pub gitlab: GitlabFileDownloader,
+    pub gist: GistFileDownloader,
+    pub zip: ZipFileDownloader,
+    pub local: LocalFileDownloader,
+    pub url: RawUrlDownloader,
+    /// Parses DSNs and downloads the files of custom schemes.
+    pub registry: SourceRegistry,
+}
+
+impl SourceDownloader {
+    pub fn new(github: GithubFileDownloader) -> Self {
+        Self {
+            github,
+            gitlab: GitlabFileDownloader::new(),
+            gist: GistFileDownloader::new(),
+            zip: ZipFileDownloader::new(),
+            local: LocalFileDownloader::new(),
+            url: RawUrlDownloader::new(),
+            registry: SourceRegistry::default(),
+        }
+    }
+
+    /// Send the requests of every remote source with `client`, e.g. one configured with a
+    /// proxy or extra root certificates.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.github = self.github.with_client(client.clone());
+        self.gitlab = self.gitlab.with_client(client.clone());
+        self.gist = self.gist.with_client(client.clone());
+        self.zip = self.zip.with_client(client.clone());
+        self.url = self.url.with_client(client);
+        self
+    }
+
+    /// Fail downloads of files larger than `max_file_size` bytes from every remote source,
+    /// instead of [`crate::DEFAULT_MAX_FILE_SIZE`].
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.github = self.github.with_max_file_size(max_file_size);
+        self.gitlab = self.gitlab.with_max_file_size(max_file_size);
+        self.gist = self.gist.with_max_file_size(max_file_size);
+        self.zip = self.zip.with_max_file_size(max_file_size);
+        self.url = self.url.with_max_file_size(max_file_size);
+        self
+    }
+
+    /// Parse DSNs with, and download the files of custom schemes through, this registry.
+    pub fn with_registry(mut self, registry: SourceRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// The URL a source is downloaded from.
+    pub fn url_for(&self, source: &FileSource) -> String {
+        match source {
+            FileSource::Github(spec) => spec.get_url_on(&self.github.raw_host),
+            FileSource::Gitlab(spec) => spec.get_url_on(&self.gitlab.base),
+            FileSource::Gist(spec) => spec.get_url_on(&self.gist.api_base),
+            FileSource::Zip(spec) => spec.url.clone(),
+            FileSource::Local(_) | FileSource::Custom(_) => source.to_string(),
+            FileSource::Url(spec) => spec.url.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl DownloadFile for SourceDownloader {
+    type FileSpec = FileSource;
+
+    fn source_url(&self, spec: &Self::FileSpec) -> Option<String> {
+        match spec {
+            FileSource::Github(spec) => self.github.source_url(spec),
+            FileSource::Gitlab(spec) => self.gitlab.source_url(spec),
+            FileSource::Gist(spec) => self.gist.source_url(spec),
+            FileSource::Zip(_) | FileSource::Local(_) | FileSource::Url(_) => None,
+            FileSource::Custom(_) => None,
+        }
+    }
+
+    async fn download_file(&self, spec: &Self::FileSpec) -> Result<Bytes, YammerError> {
+        match spec {
+            FileSource::Github(spec) => self.github.download_file(spec).await,
+            FileSource::Gitlab(spec) => self.gitlab.download_file(spec).await,
+            FileSource::Gist(spec) => self.gist.download_file(spec).await,
+            FileSource::Zip(spec) => self.zip.download_file(spec).await,
+            FileSource::Local(spec) => self.local.download_file(spec).await,
+            FileSource::Url(spec) => self.url.download_file(spec).await,
+            FileSource::Custom(spec) => {
+                self.registry
+                    .downloader(&spec.scheme)?
+                    .download_file(spec)
+                    .await
+            }
+        }
+    }
+
+    async fn download_file_if_modified(
+        &self,
+        spec: &Self::FileSpec,
+        validator: Option<&str>,
+    ) -> Result<Conditional, YammerError> {
+        match spec {
+            FileSource::Github(spec) => {
+                self.github.download_file_if_modified(spec, validator).await
+            }
+            FileSource::Gitlab(spec) => {
+                self.gitlab.download_file_if_modified(spec, validator).await
+            }
+            FileSource::Gist(spec) => self.gist.download_file_if_modified(spec, validator).await,
+            FileSource::Zip(spec) => self.zip.download_file_if_modified(spec, validator).await,
+            FileSource::Local(spec) => self.local.download_file_if_modified(spec, validator).await,
+            FileSource::Url(spec) => self.url.download_file_if_modified(spec, validator).await,
+            FileSource::Custom(spec) => {
+                self.registry
+                    .downloader(&spec.scheme)?
+                    .download_file_if_modified(spec, validator)
+                    .await
+            }
+        }
+    }
+
+    async fn download_compose_file(
+        &self,
+        spec: &Self::FileSpec,
+    ) -> Result<DockerComposeFile, YammerError> {
+        let contents = self.download_file(spec).await?;
+        DockerComposeFile::parse_with_source(&contents, &spec.to_string())
+    }
+
+    /// Like the default, but files listed more than once are only downloaded once, see
+    /// [`download_compose_files`].
+    async fn download_many(
+        &self,
+        specs: &[Self::FileSpec],
+    ) -> Vec<Result<DockerComposeFile, YammerError>> {
+        download_compose_files(self, specs, DEFAULT_MAX_CONCURRENCY).await
+    }
+}
+
+/// How many files are downloaded at once unless configured otherwise.
+pub const DEFAULT_MAX_CONCURRENCY: usize = 8;
+
+/// Download compose files, at most `max_concurrency` at a time.
+///
+/// The results come back in the order of `sources`, whatever order the downloads finish in.
+/// Sources that are listed more than once (by url) are only downloaded once, and share its
+/// result.
+pub async fn download_compose_files<'a, D>(
+    downloader: &D,
+    sources: impl IntoIterator<Item = &'a FileSource>,
+    max_concurrency: usize,
+) -> Vec<Result<DockerComposeFile, YammerError>>
+where
+    D: DownloadFile<FileSpec = FileSource> + Sync + ?Sized,
+{
+    download_compose_files_with_progress(downloader, sources, max_concurrency, &()).await
+}
+
+/// Like [`download_compose_files`], telling `progress` as each download starts and ends.
+pub async fn download_compose_files_with_progress<'a, D>(
+    downloader: &D,
+    sources: impl IntoIterator<Item = &'a FileSource>,
+    max_concurrency: usize,
+    progress: &dyn DownloadProgress,
+) -> Vec<Result<DockerComposeFile, YammerError>>
+where
+    D: DownloadFile<FileSpec = FileSource> + Sync + ?Sized,
+{
+    let keys: Vec<(String, &FileSource)> = sources
+        .into_iter()
+        .map(|source| (source.to_string(), source))
+        .collect();
+    let mut unique: HashMap<&str, &FileSource> = HashMap::new();
+    let mut uses: HashMap<&str, usize> = HashMap::new();
+    let mut order = vec![];
+    for (key, source) in &keys {
+        *uses.entry(key).or_default() += 1;
+        if unique.insert(key, source).is_none() {
+            order.push((key.as_str(), *source));
+        }
+    }
+
+    let downloads: Vec<_> = order
+        .iter()
+        .map(|&(_, source)| async move {
+            progress.started(source);
+            let started = Instant::now();
+            let compose_file = match downloader.download_file(source).await {
+                Ok(contents) => {
+                    DockerComposeFile::parse_with_source(&contents, &source.to_string())
+                }
+                Err(err) => Err(err),
+            };
+            progress.finished(source, &compose_file, started.elapsed());
+            compose_file
+        })
+        .collect();
+    let downloaded: Vec<Result<DockerComposeFile, YammerError>> = stream::iter(downloads)
+        .buffered(max_concurrency.max(1))
+        .collect()
+        .await;
+    let mut downloaded: HashMap<&str, Result<DockerComposeFile, Arc<YammerError>>> = order
+        .iter()
+        .map(|(key, _)| *key)
+        .zip(
+            downloaded
+                .into_iter()
+                .map(|result| result.map_err(Arc::new)),
+        )
+        .collect();
+
+    // The last spec to use a download takes its result, the others get copies of it.
+    keys.iter()
+        .map(|(key, _)| {
+            let uses = uses.get_mut(key.as_str()).expect("every key is counted");
+            *uses -= 1;
+            if *uses == 0 {
+                let result = downloaded
+                    .remove(key.as_str())
+                    .expect("every key is downloaded");
+                return result
+                    .map_err(|err| Arc::try_unwrap(err).unwrap_or_else(YammerError::Shared));
+            }
+            match &downloaded[key.as_str()] {
+                Ok(compose_file) => Ok(compose_file.clone()),
+                Err(err) => Err(YammerError::Shared(err.clone())),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source_names;
+    use crate::test_util::{MockResponse, MockServer, TempDir};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Serves `services: {<path>: ...}` for every local path after sleeping as many
+    /// milliseconds as the path is long, keeping track of how many downloads overlap.
+    #[derive(Default)]
+    struct Slow {
+        in_flight: AtomicUsize,
+        most_in_flight: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl DownloadFile for Slow {
+        type FileSpec = FileSource;
+
+        async fn download_file(&self, spec: &Self::FileSpec) -> Result<Bytes, YammerError> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            let path = spec.path();
+            tokio::time::sleep(Duration::from_millis(path.len() as u64 * 10)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(Bytes::from(format!(
+                "services:\n  {path}:\n    image: {path}\n"
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_compose_files_keeps_order_and_limit() {
+        let sources: Vec<FileSource> = ["aaaaaa", "aaaa", "a", "aaaaa", "aa"]
+            .map(|path| FileSource::Local(LocalFileSpec::new(path)))
+            .into();
+        let downloader = Slow::default();
+
+        let compose_files = download_compose_files(&downloader, &sources, 2).await;
+
+        let names: Vec<String> = compose_files
+            .into_iter()
+            .map(|compose_file| {
+                let services = compose_file.unwrap().services.unwrap();
+                services
+                    .keys()
+                    .next()
+                    .unwrap()
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(names, ["aaaaaa", "aaaa", "a", "aaaaa", "aa"]);
+        assert_eq!(downloader.most_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_many_files_are_fetched_concurrently_in_order() {
+        let sources: Vec<FileSource> = ["aaaa", "a", "aaa"]
+            .map(|path| FileSource::Local(LocalFileSpec::new(path)))
+            .into();
+        let downloader = Slow::default();
+
+        let compose_files = downloader.download_many(&sources).await;
+
+        assert_eq!(compose_files.len(), 3);
+        for (compose_file, source) in compose_files.iter().zip(&sources) {
+            let compose_file = compose_file.as_ref().unwrap();
+            assert!(compose_file.get_service(source.path()).is_some());
+        }
+        assert_eq!(downloader.most_in_flight.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_progress_is_reported_per_download() {
+        #[derive(Default)]
+        struct Events(std::sync::Mutex<Vec<String>>);
+
+        impl DownloadProgress for Events {
+            fn started(&self, source: &FileSource) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("start {}", source.path()));
+            }
+
+            fn finished(
+                &self,
+                source: &FileSource,
+                result: &Result<DockerComposeFile, YammerError>,
+                elapsed: Duration,
+            ) {
+                assert!(result.is_ok());
+                assert!(elapsed >= Duration::from_millis(source.path().len() as u64 * 10));
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("end {}", source.path()));
+            }
+        }
+
+        let sources: Vec<FileSource> = ["aaa", "a"]
+            .map(|path| FileSource::Local(LocalFileSpec::new(path)))
+            .into();
+        let events = Events::default();
+        download_compose_files_with_progress(&Slow::default(), &sources, 2, &events).await;
+        // The downloads run concurrently, so only which events there were is certain.
+        let mut events = events.0.into_inner().unwrap();
+        events.sort();
+        assert_eq!(events, ["end a", "end aaa", "start a", "start aaa"]);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_sources_are_fetched_once() {
+        #[derive(Default)]
+        struct Counting(std::sync::Mutex<Vec<String>>);
+
+        #[async_trait]
+        impl DownloadFile for Counting {
+            type FileSpec = FileSource;
+
+            async fn download_file(&self, spec: &Self::FileSpec) -> Result<Bytes, YammerError> {
+                self.0.lock().unwrap().push(spec.path().to_string());
+                match spec.path() {
+                    "missing.yml" => Err(YammerError::UnknownScheme("mem".to_string())),
+                    path => Ok(Bytes::from(format!(
+                        "services:\n  {path}:\n    image: {path}\n"
+                    ))),
+                }
+            }
+        }
+
+        let sources: Vec<FileSource> = ["a", "b", "a", "missing.yml", "a", "missing.yml"]
+            .map(|path| FileSource::Local(LocalFileSpec::new(path)))
+            .into();
+        let downloader = Counting::default();
+
+        let compose_files = download_compose_files(&downloader, &sources, 4).await;
+
+        assert_eq!(
+            downloader.0.into_inner().unwrap(),
+            ["a", "b", "missing.yml"]
+        );
+        assert_eq!(compose_files.len(), 6);
+        for index in [0, 2, 4] {
+            assert!(
+                compose_files[index]
+                    .as_ref()
+                    .unwrap()
+                    .get_service("a")
+                    .is_some()
+            );
+        }
+        assert!(
+            compose_files[1]
+                .as_ref()
+                .unwrap()
+                .get_service("b")
+                .is_some()
+        );
+        for index in [3, 5] {
+            let err = compose_files[index].as_ref().unwrap_err();
+            assert!(matches!(err, YammerError::Shared(_)));
+            assert_eq!(
+                err.to_string(),
+                "No downloader is registered for the `mem:` scheme"
+            );
+        }
+    }
+
+    #[test]
+    fn test_compose_service_spec_from_str_gist() {
+        let spec: ComposeServiceSpec = "gist:abc123:docker-compose.yml@redis,mongo"
+            .parse()
+            .unwrap();
+        let FileSource::Gist(gist) = spec.source else {
+            panic!("expected a gist source");
+        };
+        assert_eq!(gist, GistFileSpec::new("abc123", "docker-compose.yml"));
+        assert_eq!(source_names(&spec.services), ["redis", "mongo"]);
+
+        assert!("gist:abc123@redis".parse::<ComposeServiceSpec>().is_err());
+        let spec: ComposeServiceSpec = "gist:abc123:docker-compose.yml".parse().unwrap();
+        assert!(spec.services.is_empty());
+    }
+
+    #[test]
+    fn test_compose_service_spec_from_str_github() {
+        let spec: ComposeServiceSpec = "Data4Democracy/docker-scaffolding:docker-compose.yml@mongo"
+            .parse()
+            .unwrap();
+        assert!(matches!(spec.source, FileSource::Github(_)));
+        assert_eq!(source_names(&spec.services), ["mongo"]);
+    }
+
+    #[test]
+    fn test_compose_service_spec_from_str_zip() {
+        let spec: ComposeServiceSpec =
+            "zip:https://user:pw@ci.example.com/a.zip!deploy/docker-compose.yml@redis,mongo"
+                .parse()
+                .unwrap();
+        let FileSource::Zip(zip) = &spec.source else {
+            panic!("expected a zip source");
+        };
+        assert_eq!(zip.url, "https://user:pw@ci.example.com/a.zip");
+        assert_eq!(zip.entry, "deploy/docker-compose.yml");
+        assert_eq!(source_names(&spec.services), ["redis", "mongo"]);
+
+        assert!(
+            "zip:https://ci.example.com/a.zip@redis"
+                .parse::<ComposeServiceSpec>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_compose_service_spec_from_str_overrides() {
+        let spec: ComposeServiceSpec =
+            "org/repo:docker-compose.yml,docker-compose.override.yml@web"
+                .parse()
+                .unwrap();
+        assert_eq!(spec.source.path(), "docker-compose.yml");
+        assert_eq!(spec.overrides.len(), 1);
+        let FileSource::Github(layer) = &spec.overrides[0] else {
+            panic!("expected a github override");
+        };
+        assert_eq!(layer.filepath, "docker-compose.override.yml");
+        assert_eq!(layer.branch, "master");
+        assert_eq!(spec.files().count(), 2);
+    }
+
+    #[test]
+    fn test_every_source_kind_takes_patches() {
+        for (dsn, path) in [
+            (
+                "https://example.com/dc.yml@pg{image=ghcr.io/org/pg:16}",
+                "https://example.com/dc.yml",
+            ),
+            (
+                "file://./dc.yml@pg{image=postgres:16@sha256:abc}",
+                "./dc.yml",
+            ),
+            (
+                "gitlab:group/project+main:dc.yml@pg{image=postgres:16@sha256:abc}",
+                "dc.yml",
+            ),
+            ("gist:abc123:dc.yml@pg{image=ghcr.io/org/pg:16}", "dc.yml"),
+            (
+                "zip:https://ci.example.com/a.zip!deploy/dc.yml@pg{image=postgres:16@sha256:abc}",
+                "deploy/dc.yml",
+            ),
+        ] {
+            let spec: ComposeServiceSpec = dsn.parse().unwrap();
+            assert_eq!(spec.source.path(), path, "{dsn}");
+            assert_eq!(source_names(&spec.services), ["pg"], "{dsn}");
+            assert_eq!(spec.services[0].patches.len(), 1, "{dsn}");
+            assert_eq!(spec.to_string(), dsn);
+        }
+    }
+
+    #[test]
+    fn test_compose_service_spec_display_round_trips() {
+        for dsn in [
+            "Data4Democracy/docker-scaffolding+main:docker-compose.yml@mongo,redis",
+            "gist:abc123:docker-compose.yml@redis",
+            "gist:abc123:docker-compose.yml",
+            "org/repo+main:docker-compose.yml,docker-compose.override.yml@web",
+            "zip:https://ci.example.com/artifacts/1.zip!deploy/docker-compose.yml@redis",
+            "org/repo+tag:v1.2.3:docker-compose.yml@redis",
+            "file://./deploy/docker-compose.yml@redis",
+            "https://artifacts.example.com/compose/docker-compose.yml@redis",
+            "gitlab:group/sub/project+main:docker-compose.yml,docker-compose.ci.yml@redis",
+            "org/repo+sha:abc123@redis",
+        ] {
+            let spec: ComposeServiceSpec = dsn.parse().unwrap();
+            assert_eq!(spec.to_string(), dsn);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_source_downloader_routes_gist_dsn() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/gists/abc123" => MockResponse::ok(
+                r#"{"files": {"docker-compose.yml": {
+                    "raw_url": "unused",
+                    "content": "services:\n  redis:\n    image: redis\n"
+                }}}"#,
+            ),
+            _ => MockResponse::new(404, "{}"),
+        })
+        .await;
+        let downloader = SourceDownloader {
+            github: GithubFileDownloader::new().with_raw_host("http://127.0.0.1:9"),
+            gist: GistFileDownloader::new().with_api_base(server.url()),
+            ..SourceDownloader::default()
+        };
+        let spec: ComposeServiceSpec = "gist:abc123:docker-compose.yml@redis".parse().unwrap();
+
+        let compose_file = downloader
+            .download_compose_file(&spec.source)
+            .await
+            .unwrap();
+        assert!(
+            compose_file
+                .get_service(&spec.services[0].source_name)
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spec_without_path_falls_back_to_default_compose_file() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/org/repo/refs/heads/main/compose.yaml" => {
+                MockResponse::ok("services:\n  redis:\n    image: redis\n")
+            }
+            _ => MockResponse::new(404, "not found"),
+        })
+        .await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+
+        let spec: ComposeServiceSpec = "org/repo+main@redis".parse().unwrap();
+        assert_eq!(spec.source.path(), "");
+        assert_eq!(source_names(&spec.services), ["redis"]);
+        assert_eq!(spec.to_string(), "org/repo+main@redis");
+
+        let compose_file = downloader
+            .download_compose_file(&spec.source)
+            .await
+            .unwrap();
+        assert!(compose_file.get_service("redis").is_some());
+        assert_eq!(
+            server.hits("/org/repo/refs/heads/main/docker-compose.yml"),
+            1
+        );
+        assert_eq!(server.total_hits(), 2);
+
+        let missing: ComposeServiceSpec = "org/missing@redis".parse().unwrap();
+        let err = downloader
+            .download_compose_file(&missing.source)
+            .await
+            .unwrap_err();
+        assert_eq!(err.http_status(), Some(reqwest::StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_parse_errors_name_the_file() {
+        let server =
+            MockServer::start(|_| MockResponse::ok("services:\n  redis: [unclosed\n")).await;
+        let downloader =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let spec: ComposeServiceSpec = "org/repo+main:docker-compose.yml@redis".parse().unwrap();
+
+        let err = downloader
+            .download_compose_file(&spec.source)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, YammerError::Parse { file, .. } if file.ends_with("/org/repo/refs/heads/main/docker-compose.yml")),
+            "{err:?}"
+        );
+        assert!(err.to_string().starts_with("Failed to parse https://"));
+
+        let results = download_compose_files(&downloader, [&spec.source], 1).await;
+        assert!(matches!(results[0], Err(YammerError::Parse { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_source_downloader_routes_local_and_url_dsns() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/compose/docker-compose.yml" => {
+                MockResponse::ok("services:\n  api:\n    image: api\n")
+            }
+            "/compose/docker-compose.override.yml" => {
+                MockResponse::ok("services:\n  api:\n    image: api:dev\n")
+            }
+            _ => MockResponse::new(404, "Not Found"),
+        })
+        .await;
+        let dir = TempDir::new("source");
+        let path = dir.join("docker-compose.yml");
+        std::fs::write(&path, "services:\n  redis:\n    image: redis\n").unwrap();
+        let downloader = SourceDownloader::default();
+
+        let local: ComposeServiceSpec = format!("file://{}@redis", path.display()).parse().unwrap();
+        assert!(matches!(&local.source, FileSource::Local(spec) if spec.path == path));
+        let layered: ComposeServiceSpec = format!("file://{},override.yml@redis", path.display())
+            .parse()
+            .unwrap();
+        assert!(matches!(&layered.source, FileSource::Local(spec) if spec.path == path));
+        assert!(matches!(
+            layered.overrides.as_slice(),
+            [FileSource::Local(spec)] if spec.path == path.with_file_name("override.yml")
+        ));
+        let compose_file = downloader
+            .download_compose_file(&local.source)
+            .await
+            .unwrap();
+        assert!(compose_file.get_service("redis").is_some());
+
+        let url: ComposeServiceSpec = format!(
+            "{}/compose/docker-compose.yml,docker-compose.override.yml@api",
+            server.url()
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(source_names(&url.services), ["api"]);
+        let [FileSource::Url(layer)] = url.overrides.as_slice() else {
+            panic!("expected a url override");
+        };
+        assert_eq!(
+            layer.url,
+            format!("{}/compose/docker-compose.override.yml", server.url())
+        );
+        for source in url.files() {
+            assert!(downloader.download_compose_file(source).await.is_ok());
+        }
+        assert_eq!(server.total_hits(), 2);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{YammerError, materialize_merge_keys};
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use yaml_rust2::ScanError;
+use yaml_rust2::parser::{Event, Parser};
+use yaml_rust2::scanner::{Marker, TScalarStyle};
+
+/// Walks the YAML events of a compose file, only building values for the parts it's asked to.
+struct Extractor<'a> {
+    parser: Parser<std::str::Chars<'a>>,
+    /// Every anchored node seen so far, since the service being extracted may alias any of them.
+    anchors: HashMap<usize, Value>,
+}
+
+impl<'a> Extractor<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            parser: Parser::new_from_str(text),
+            anchors: HashMap::new(),
+        }
+    }
+
+    fn next(&mut self) -> Result<(Event, Marker), ScanError> {
+        self.parser.next_token()
+    }
+
+    fn anchor(&mut self, id: usize, value: &Value) {
+        if id > 0 {
+            self.anchors.insert(id, value.clone());
+        }
+    }
+
+    /// Build the whole node that starts with `event`.
+    fn build(&mut self, event: Event, mark: Marker) -> Result<Value, ScanError> {
+        match event {
+            Event::Scalar(value, style, anchor, _) => {
+                let value = scalar(value, style);
+                self.anchor(anchor, &value);
+                Ok(value)
+            }
+            Event::Alias(id) => self
+                .anchors
+                .get(&id)
+                .cloned()
+                .ok_or_else(|| ScanError::new(mark, "alias to an unknown anchor")),
+            Event::SequenceStart(anchor, _) => {
+                let mut sequence = vec![];
+                loop {
+                    let (event, mark) = self.next()?;
+                    if event == Event::SequenceEnd {
+                        break;
+                    }
+                    sequence.push(self.build(event, mark)?);
+                }
+                let value = Value::Sequence(sequence);
+                self.anchor(anchor, &value);
+                Ok(value)
+            }
+            Event::MappingStart(anchor, _) => {
+                let mut mapping = Mapping::new();
+                loop {
+                    let (event, mark) = self.next()?;
+                    if event == Event::MappingEnd {
+                        break;
+                    }
+                    let key = self.build(event, mark)?;
+                    let (event, mark) = self.next()?;
+                    let value = self.build(event, mark)?;
+                    mapping.insert(key, value);
+                }
+                let value = Value::Mapping(mapping);
+                self.anchor(anchor, &value);
+                Ok(value)
+            }
+            _ => Err(ScanError::new(mark, "unexpected YAML event")),
+        }
+    }
+
+    /// Skip over the node that starts with `event`, only building the anchored nodes in it.
+    fn skip(&mut self, event: Event, mark: Marker) -> Result<(), ScanError> {
+        match event {
+            Event::Scalar(_, _, anchor, _)
+            | Event::SequenceStart(anchor, _)
+            | Event::MappingStart(anchor, _)
+                if anchor > 0 =>
+            {
+                self.build(event, mark).map(|_| ())
+            }
+            Event::SequenceStart(..) | Event::MappingStart(..) => loop {
+                let (event, mark) = self.next()?;
+                if matches!(event, Event::SequenceEnd | Event::MappingEnd) {
+                    return Ok(());
+                }
+                self.skip(event, mark)?;
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Go through the entries of the mapping that was just started, building the value under
+    /// `key` and skipping everything else. Stops right after that value.
+    fn find_in_mapping(
+        &mut self,
+        key: &str,
+        mut found: impl FnMut(&mut Self, Event, Marker) -> Result<Option<Value>, ScanError>,
+    ) -> Result<Option<Value>, ScanError> {
+        loop {
+            let (event, mark) = self.next()?;
+            if event == Event::MappingEnd {
+                return Ok(None);
+            }
+            let entry_key = self.build(event, mark)?;
+            let (event, mark) = self.next()?;
+            if entry_key.as_str() == Some(key) {
+                return found(self, event, mark);
+            }
+            self.skip(event, mark)?;
+        }
+    }
+}
+
+/// Resolve a scalar the way serde_yaml would: quoted scalars are strings, and plain ones can be
+/// null, booleans or numbers.
+fn scalar(value: String, style: TScalarStyle) -> Value {
+    if style != TScalarStyle::Plain {
+        return Value::String(value);
+    }
+    match serde_yaml::from_str::<Value>(&value) {
+        Ok(resolved @ (Value::Null | Value::Bool(_) | Value::Number(_))) => resolved,
+        _ => Value::String(value),
+    }
+}
+
+/// Pull a single service out of a compose file by scanning its YAML events, without
+/// deserializing the rest of the file into a [`Mapping`].
+///
+/// Scanning stops as soon as the service has been read, so this is much cheaper than a full
+/// parse for a service near the top of a big file. Only anchored nodes are kept along the way,
+/// so the service's aliases and merge keys resolve just like with
+/// [`DockerComposeFile::get_service`](crate::DockerComposeFile::get_service). Tags are ignored.
+pub fn extract_service(text: &str, name: &str) -> Result<Option<Mapping>, YammerError> {
+    let mut extractor = Extractor::new(text);
+    let service = loop {
+        let (event, _) = extractor.next()?;
+        match event {
+            Event::StreamStart | Event::DocumentStart => continue,
+            Event::MappingStart(..) => {
+                break extractor.find_in_mapping(
+                    "services",
+                    |extractor, event, mark| match event {
+                        Event::MappingStart(..) => extractor
+                            .find_in_mapping(name, |extractor, event, mark| {
+                                extractor.build(event, mark).map(Some)
+                            }),
+                        event => extractor.skip(event, mark).map(|_| None),
+                    },
+                )?;
+            }
+            _ => break None,
+        }
+    };
+    let Some(mut service) = service else {
+        return Ok(None);
+    };
+    materialize_merge_keys(&mut service)?;
+    match service {
+        Value::Mapping(service) => Ok(Some(service)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DockerComposeFile;
+    use bytes::Bytes;
+
+    fn synthetic_compose_file(services: usize) -> String {
+        let mut text = String::from(
+            "version: '3.8'\nx-base: &base\n  restart: always\n  environment:\n    TZ: UTC\n\nservices:\n",
+        );
+        for i in 0..services {
+            text.push_str(&format!(
+                r#"  svc-{i}:
+    <<: *base
+    image: "example/svc:{i}"
+    command: ["run", "--port", "{i}"]
+    ports:
+      - "{port}:80"
+    healthcheck:
+      test: [CMD, curl, -f, http://localhost]
+      interval: 30s
+      retries: 3
+    deploy:
+      replicas: {replicas}
+      resources: {{limits: {{cpus: '0.5'}}}}
+    read_only: {read_only}
+    labels:
+      quoted: "true"
+      empty:
+"#,
+                port = 10000 + i,
+                replicas = i % 4,
+                read_only = i % 2 == 0,
+            ));
+        }
+        text.push_str("networks:\n  default: {}\n");
+        text
+    }
+
+    #[test]
+    fn test_extract_service_matches_full_parse() {
+        let text = synthetic_compose_file(2000);
+        let full = DockerComposeFile::try_from(&Bytes::from(text.clone())).unwrap();
+
+        for name in ["svc-0", "svc-1337", "svc-1999"] {
+            let streamed = extract_service(&text, name).unwrap().unwrap();
+            assert_eq!(Some(&streamed), full.get_service(name), "{name}");
+        }
+        assert_eq!(extract_service(&text, "svc-2000").unwrap(), None);
+    }
+
+    #[test]
+    fn test_extract_service_without_services() {
+        assert_eq!(extract_service("version: '3'\n", "redis").unwrap(), None);
+        assert_eq!(extract_service("", "redis").unwrap(), None);
+    }
+}
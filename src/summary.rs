@@ -0,0 +1,158 @@
+use crate::{CacheStatus, MergeReport, SpecId};
+use serde::{Serialize, Serializer};
+use std::time::Duration;
+
+/// Serialize a duration as a whole number of milliseconds.
+pub(crate) fn serialize_millis<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration
+        .map(|duration| duration.as_millis() as u64)
+        .serialize(serializer)
+}
+
+/// How downloading and selecting services from one spec's source went.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceSummary {
+    pub spec: SpecId,
+    pub url: String,
+    /// The HTTP status a download failed with, if it failed over HTTP. Downloads that
+    /// succeeded don't report theirs, since not every source has one.
+    pub status: Option<u16>,
+    /// The size of the downloaded file, if it was downloaded.
+    pub bytes: Option<usize>,
+    /// How many services the file defines.
+    pub services_found: usize,
+    /// How many of the selected services the file actually defines.
+    pub services_selected: usize,
+    /// Whether the file came from the download cache, if one was used.
+    pub cache: Option<CacheStatus>,
+}
+
+impl MergeReport {
+    /// Render [`MergeReport::sources`] as a table with aligned columns, one row per source.
+    pub fn summary_table(&self) -> String {
+        let header = ["SOURCE", "URL", "STATUS", "BYTES", "SERVICES", "CACHE"].map(String::from);
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        let rows: Vec<[String; 6]> = std::iter::once(header)
+            .chain(self.sources.iter().map(|source| {
+                [
+                    source.spec.to_string(),
+                    source.url.clone(),
+                    or_dash(source.status.map(|status| status.to_string())),
+                    or_dash(source.bytes.map(|bytes| bytes.to_string())),
+                    format!("{}/{}", source.services_selected, source.services_found),
+                    or_dash(source.cache.map(|cache| cache.to_string())),
+                ]
+            }))
+            .collect();
+
+        let mut widths = [0; 6];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let mut table = String::new();
+        for row in rows {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect();
+            table.push_str(cells.join("  ").trim_end());
+            table.push('\n');
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer, TempDir};
+    use crate::{CachingDownloader, DownloadFile, FileSource, SourceDownloader};
+    use crate::{ComposeServiceSpec, GithubFileDownloader};
+
+    #[tokio::test]
+    async fn test_summary_table_marks_cache_hits() {
+        let server = MockServer::start(|_| {
+            MockResponse::ok("services:\n  redis:\n    image: redis\n  mongo:\n    image: mongo\n")
+        })
+        .await;
+        let dir = TempDir::new("summary");
+        let sources =
+            SourceDownloader::new(GithubFileDownloader::new().with_raw_host(server.url()));
+        let spec: ComposeServiceSpec = "org/repo+main:docker-compose.yml@redis".parse().unwrap();
+        let FileSource::Github(source) = &spec.source else {
+            unreachable!()
+        };
+        let downloader = CachingDownloader::new(sources.github.clone(), dir.path());
+
+        let mut report = MergeReport::default();
+        for index in 0..2 {
+            let contents = downloader.download_compose_file(source).await.unwrap();
+            report.sources.push(SourceSummary {
+                spec: spec.id(index),
+                url: sources.url_for(&spec.source),
+                status: None,
+                bytes: contents.raw.as_ref().map(String::len),
+                services_found: contents.services.map_or(0, |services| services.len()),
+                services_selected: 1,
+                cache: downloader.cache_status(source),
+            });
+        }
+
+        let table = report.summary_table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("SOURCE"));
+        assert!(
+            lines[1].contains("1/2") && lines[1].ends_with("miss"),
+            "{table}"
+        );
+        assert!(lines[2].ends_with("hit"), "{table}");
+        assert!(lines[2].contains("/org/repo/refs/heads/main/docker-compose.yml"));
+    }
+
+    #[test]
+    fn test_summary_json_has_sources_and_conflicts() {
+        let spec = SpecId {
+            index: 0,
+            spec: "org/repo+main:docker-compose.yml@redis".to_string(),
+        };
+        let report = MergeReport {
+            env_conflicts: vec![crate::EnvConflict {
+                service: "redis".to_string(),
+                key: "TZ".to_string(),
+                earlier: Some("UTC".to_string()),
+                later: Some("CET".to_string()),
+                kept: Some("CET".to_string()),
+            }],
+            contributors: [("redis".to_string(), vec![spec.clone()])].into(),
+            sources: vec![SourceSummary {
+                spec,
+                url: "https://example.com/docker-compose.yml".to_string(),
+                status: Some(404),
+                bytes: None,
+                services_found: 0,
+                services_selected: 0,
+                cache: None,
+            }],
+            pruned: vec!["legacy".to_string()],
+            elapsed: Some(Duration::from_millis(1500)),
+        };
+
+        let json = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(json["sources"][0]["status"], 404);
+        assert!(json["sources"][0]["cache"].is_null());
+        assert_eq!(json["sources"][0]["spec"]["index"], 0);
+        assert_eq!(json["env_conflicts"][0]["key"], "TZ");
+        assert_eq!(json["env_conflicts"][0]["kept"], "CET");
+        assert_eq!(json["contributors"]["redis"][0]["index"], 0);
+        assert_eq!(json["pruned"][0], "legacy");
+        assert_eq!(json["elapsed_ms"], 1500);
+    }
+}
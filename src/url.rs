@@ -0,0 +1,121 @@
+use crate::{DEFAULT_MAX_FILE_SIZE, DownloadFile, YammerError};
+use async_trait::async_trait;
+use bytes::Bytes;
+
+/// A file served at a plain HTTP(S) url, e.g. by an internal artifact server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawUrlSpec {
+    pub url: String,
+}
+
+impl RawUrlSpec {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+}
+
+/// Downloads files from arbitrary urls.
+#[derive(Debug, Clone)]
+pub struct RawUrlDownloader {
+    pub client: reqwest::Client,
+    /// The most bytes a downloaded file may have.
+    pub max_file_size: u64,
+}
+
+impl RawUrlDownloader {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+}
+
+impl Default for RawUrlDownloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl DownloadFile for RawUrlDownloader {
+    type FileSpec = RawUrlSpec;
+
+    async fn download_file(&self, spec: &Self::FileSpec) -> Result<Bytes, YammerError> {
+        let response = self
+            .client
+            .get(&spec.url)
+            .header(reqwest::header::USER_AGENT, crate::USER_AGENT)
+            .send()
+            .await?;
+        let response = crate::refusal(&spec.url, response)
+            .await?
+            .error_for_status()?;
+        crate::read_body(&spec.url, response, self.max_file_size).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer};
+
+    #[tokio::test]
+    async fn test_download_from_raw_url() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/compose/docker-compose.yml" => {
+                MockResponse::ok("services:\n  redis:\n    image: redis\n")
+            }
+            _ => MockResponse::new(404, "Not Found"),
+        })
+        .await;
+        let downloader = RawUrlDownloader::new();
+
+        let compose_file = downloader
+            .download_compose_file(&RawUrlSpec::new(format!(
+                "{}/compose/docker-compose.yml",
+                server.url()
+            )))
+            .await
+            .unwrap();
+        assert_eq!(compose_file.get_service("redis").unwrap()["image"], "redis");
+
+        let missing = downloader
+            .download_file(&RawUrlSpec::new(format!("{}/missing.yml", server.url())))
+            .await
+            .unwrap_err();
+        assert_eq!(missing.http_status(), Some(reqwest::StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_files_over_the_size_limit_are_refused() {
+        let server = MockServer::start(|_| MockResponse::ok("services: {}\n".repeat(10))).await;
+        let spec = RawUrlSpec::new(format!("{}/docker-compose.yml", server.url()));
+
+        let err = RawUrlDownloader::new()
+            .with_max_file_size(100)
+            .download_file(&spec)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, YammerError::TooLarge { url, limit: 100 } if *url == spec.url),
+            "{err:?}"
+        );
+        let contents = RawUrlDownloader::new()
+            .with_max_file_size(130)
+            .download_file(&spec)
+            .await
+            .unwrap();
+        assert_eq!(contents.len(), 130);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{dependency_cycles, service_dependencies};
+use std::fmt::Display;
+
+/// A problem found in a merged set of services that docker would reject.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// `service` depends on `dependency`, which isn't one of the merged services.
+    MissingDependency { service: String, dependency: String },
+    /// The services depend on each other in a cycle, each on the next and the last on the first.
+    DependencyCycle { services: Vec<String> },
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationIssue::MissingDependency {
+                service,
+                dependency,
+            } => write!(
+                f,
+                "service `{service}` depends on `{dependency}`, which is not in the merged services (select `{dependency}` as well, or pass --with-deps)"
+            ),
+            ValidationIssue::DependencyCycle { services } => {
+                let path: Vec<String> = services
+                    .iter()
+                    .chain(services.first())
+                    .map(|service| format!("`{service}`"))
+                    .collect();
+                write!(
+                    f,
+                    "services depend on each other in a cycle: {}",
+                    path.join(" -> ")
+                )
+            }
+        }
+    }
+}
+
+/// Check a merged `services` mapping for references docker wouldn't be able to resolve, and
+/// for `depends_on` cycles.
+pub fn validate_services(services: &serde_yaml::Mapping) -> Vec<ValidationIssue> {
+    let mut issues = vec![];
+    for (name, service) in services {
+        let (Some(name), Some(service)) = (name.as_str(), service.as_mapping()) else {
+            continue;
+        };
+        for dependency in service_dependencies(service) {
+            if !services.contains_key(dependency.as_str()) {
+                issues.push(ValidationIssue::MissingDependency {
+                    service: name.to_string(),
+                    dependency,
+                });
+            }
+        }
+    }
+    issues.extend(
+        dependency_cycles(services)
+            .into_iter()
+            .map(|services| ValidationIssue::DependencyCycle { services }),
+    );
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_services_flags_unselected_dependency() {
+        let services: serde_yaml::Mapping = serde_yaml::from_str(
+            r#"
+            api:
+              image: api
+              depends_on:
+                - db
+                - cache
+            cache:
+              image: redis"#,
+        )
+        .unwrap();
+
+        let issues = validate_services(&services);
+        assert_eq!(
+            issues,
+            vec![ValidationIssue::MissingDependency {
+                service: "api".to_string(),
+                dependency: "db".to_string(),
+            }]
+        );
+        assert!(issues[0].to_string().contains("`db`"));
+    }
+
+    #[test]
+    fn test_validate_services_all_present() {
+        let services: serde_yaml::Mapping = serde_yaml::from_str(
+            r#"
+            api:
+              depends_on:
+                db:
+                  condition: service_healthy
+            db:
+              image: postgres"#,
+        )
+        .unwrap();
+        assert!(validate_services(&services).is_empty());
+    }
+
+    #[test]
+    fn test_validate_services_reports_dependency_cycle() {
+        let services: serde_yaml::Mapping = serde_yaml::from_str(
+            r#"
+            web:
+              depends_on: [api]
+            api:
+              depends_on:
+                worker:
+                  condition: service_started
+            worker:
+              depends_on: [api]"#,
+        )
+        .unwrap();
+
+        let issues = validate_services(&services);
+        assert_eq!(
+            issues,
+            vec![ValidationIssue::DependencyCycle {
+                services: vec!["api".to_string(), "worker".to_string()],
+            }]
+        );
+        assert_eq!(
+            issues[0].to_string(),
+            "services depend on each other in a cycle: `api` -> `worker` -> `api`"
+        );
+    }
+}
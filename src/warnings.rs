@@ -0,0 +1,57 @@
+use crate::YammerError;
+use std::fmt::Display;
+
+/// Collects the warnings of a run as they're printed, so a strict run can fail on them.
+#[derive(Debug, Clone, Default)]
+pub struct Warnings {
+    messages: Vec<String>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Print a warning to stderr and remember it.
+    pub fn warn(&mut self, message: impl Display) {
+        let message = message.to_string();
+        eprintln!("warning: {message}");
+        self.messages.push(message);
+    }
+
+    pub fn messages(&self) -> &[String] {
+        &self.messages
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Fail with [`YammerError::WarningsAsErrors`] if `as_errors` is set and anything was warned
+    /// about.
+    pub fn check(&self, as_errors: bool) -> Result<(), YammerError> {
+        if as_errors && !self.is_empty() {
+            return Err(YammerError::WarningsAsErrors(self.messages.len()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_only_fails_with_warnings_as_errors() {
+        let mut warnings = Warnings::new();
+        assert!(warnings.check(true).is_ok());
+
+        warnings.warn("service `redis` not found");
+        assert_eq!(warnings.messages(), ["service `redis` not found"]);
+        assert!(warnings.check(false).is_ok());
+        assert!(matches!(
+            warnings.check(true),
+            Err(YammerError::WarningsAsErrors(1))
+        ));
+    }
+}
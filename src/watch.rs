@@ -0,0 +1,170 @@
+use crate::{Conditional, DockerComposeFile, DownloadFile, YammerError};
+use bytes::Bytes;
+
+/// Keeps track of the last seen contents of a set of sources, so they can be cheaply
+/// re-checked for changes with conditional downloads.
+pub struct Watcher<D: DownloadFile> {
+    downloader: D,
+    sources: Vec<WatchedSource<D::FileSpec>>,
+}
+
+/// What a [`Watcher::poll`] found.
+#[derive(Debug, Default)]
+pub struct Polled {
+    /// Whether the contents of any source changed.
+    pub changed: bool,
+    /// Why the sources that couldn't be checked couldn't be.
+    pub errors: Vec<YammerError>,
+}
+
+struct WatchedSource<F> {
+    spec: F,
+    validator: Option<String>,
+    contents: Option<Bytes>,
+}
+
+impl<D: DownloadFile + Sync> Watcher<D> {
+    pub fn new(downloader: D, specs: Vec<D::FileSpec>) -> Self {
+        let sources = specs
+            .into_iter()
+            .map(|spec| WatchedSource {
+                spec,
+                validator: None,
+                contents: None,
+            })
+            .collect();
+        Self {
+            downloader,
+            sources,
+        }
+    }
+
+    /// Re-check every source, returning whether the contents of any of them changed since the
+    /// last poll. The first poll always reports a change.
+    ///
+    /// Sources that can't be checked keep their last seen contents, and don't stop the others
+    /// from being checked. Their errors are collected in [`Polled::errors`].
+    pub async fn poll(&mut self) -> Polled {
+        let mut polled = Polled::default();
+        for source in &mut self.sources {
+            let validator = source.contents.as_ref().and(source.validator.as_deref());
+            match self
+                .downloader
+                .download_file_if_modified(&source.spec, validator)
+                .await
+            {
+                Ok(Conditional::NotModified) => {}
+                Ok(Conditional::Modified {
+                    contents,
+                    validator,
+                }) => {
+                    polled.changed |= source.contents.as_ref() != Some(&contents);
+                    source.contents = Some(contents);
+                    source.validator = validator;
+                }
+                Err(err) => polled.errors.push(err),
+            }
+        }
+        polled
+    }
+
+    /// Parse the last seen contents of every source, in the order the specs were given.
+    pub fn compose_files(&self) -> Vec<Result<DockerComposeFile, YammerError>> {
+        self.sources
+            .iter()
+            .map(|source| match &source.contents {
+                Some(contents) => DockerComposeFile::parse(contents),
+                None => Err(YammerError::NotYetDownloaded),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GithubFileDownloader;
+    use crate::test_util::{MockResponse, MockServer, github_spec};
+
+    #[tokio::test]
+    async fn test_watcher_only_reports_changed_contents() {
+        let server = MockServer::start(|request| {
+            let if_none_match = request.headers.get("if-none-match").map(String::as_str);
+            match (request.hit, if_none_match) {
+                (1, None) => {
+                    MockResponse::ok("services: {a: {image: a}}").with_header("ETag", "\"v1\"")
+                }
+                (2, Some("\"v1\"")) => MockResponse::new(304, ""),
+                (3, Some("\"v1\"")) => {
+                    MockResponse::ok("services: {b: {image: b}}").with_header("ETag", "\"v2\"")
+                }
+                (4, Some("\"v2\"")) => MockResponse::new(304, ""),
+                _ => MockResponse::new(400, "unexpected request"),
+            }
+        })
+        .await;
+        let downloader = GithubFileDownloader::new().with_raw_host(server.url());
+        let spec = github_spec("repo", "docker-compose.yml");
+        let mut watcher = Watcher::new(downloader, vec![spec]);
+
+        let mut merges = vec![];
+        for _ in 0..4 {
+            if watcher.poll().await.changed {
+                let file = watcher.compose_files().remove(0).unwrap();
+                let services = file.services.unwrap();
+                merges.extend(
+                    services
+                        .keys()
+                        .filter_map(|name| name.as_str())
+                        .map(str::to_string),
+                );
+            }
+        }
+
+        assert_eq!(merges, vec!["a", "b"]);
+        assert_eq!(server.total_hits(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_watcher_compares_contents_without_validators() {
+        let server = MockServer::start(|request| match request.hit {
+            1 | 2 => MockResponse::ok("services: {a: {image: a}}"),
+            _ => MockResponse::ok("services: {a: {image: a2}}"),
+        })
+        .await;
+        let downloader = GithubFileDownloader::new().with_raw_host(server.url());
+        let spec = github_spec("repo", "docker-compose.yml");
+        let mut watcher = Watcher::new(downloader, vec![spec]);
+
+        assert!(watcher.poll().await.changed);
+        assert!(!watcher.poll().await.changed);
+        assert!(watcher.poll().await.changed);
+    }
+
+    #[tokio::test]
+    async fn test_watcher_keeps_polling_past_failing_sources() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/org/repo/refs/heads/main/broken.yml" => MockResponse::new(500, "down"),
+            _ => MockResponse::ok("services: {a: {image: a}}"),
+        })
+        .await;
+        let downloader = GithubFileDownloader::new()
+            .with_raw_host(server.url())
+            .with_retry_policy(crate::RetryPolicy {
+                max_retries: 0,
+                ..Default::default()
+            });
+        let spec = |path: &str| github_spec("repo", path);
+        let mut watcher = Watcher::new(downloader, vec![spec("broken.yml"), spec("ok.yml")]);
+
+        let polled = watcher.poll().await;
+        assert!(polled.changed);
+        assert_eq!(polled.errors.len(), 1);
+        let compose_files = watcher.compose_files();
+        assert!(matches!(
+            compose_files[0],
+            Err(YammerError::NotYetDownloaded)
+        ));
+        assert!(compose_files[1].is_ok());
+    }
+}